@@ -9,5 +9,9 @@ lib.recurseIntoAttrs (
     makePackages = final.callPackage ./makePackages.nix { };
 
     makeProfile = final.callPackage ./makeProfile.nix { };
+
+    readPackagesFile = final.callPackage ./readPackagesFile.nix { };
+
+    packageInfo = final.callPackage ./packageInfo.nix { };
   })
 )
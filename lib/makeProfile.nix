@@ -1,20 +1,66 @@
 {
+  lib,
+  pkgs,
   makePins,
   makePackages,
+  readPackagesFile,
+  packageInfo,
 }:
 
 {
   pins ? { },
-  paths ? { },
+  paths ? [ ],
   makePackagesArgs ? { },
+  # Extra profiles, switched independently of `packages`. Each value is an
+  # attrset of `makePackages` arguments, e.g. `{ paths = [ ... ]; }`.
+  profiles ? { },
+  # A `packages.toml` file listing more `paths` by attribute name, which
+  # `npingler add` and `npingler remove` edit.
+  packagesFile ? null,
+  # The host whose `[host.<host>]` section of `packagesFile` to use.
+  host ? null,
 }:
 
-{
-  pins = makePins pins;
-  packages = makePackages (
+let
+  packagesArgs = {
+    paths =
+      paths
+      ++ lib.optionals (packagesFile != null) (readPackagesFile {
+        file = packagesFile;
+        inherit host;
+      });
+  }
+  // makePackagesArgs;
+
+  profilesArgs = builtins.mapAttrs (
+    name: args:
     {
-      inherit paths;
+      name = "npingler-packages-${name}";
     }
-    // makePackagesArgs
+    // args
+  ) profiles;
+in
+
+{
+  pins = makePins pins;
+  packages = makePackages packagesArgs;
+  profiles = builtins.mapAttrs (name: makePackages) profilesArgs;
+
+  # Metadata for the packages in each profile, for `npingler list`.
+  packageInfo = {
+    packages = packageInfo (packagesArgs.paths or [ ]);
+    profiles = builtins.mapAttrs (name: args: packageInfo (args.paths or [ ])) profilesArgs;
+  };
+
+  # Get the attribute names which aren't packages in this profile's `nixpkgs`,
+  # for `npingler add`.
+  missingPackages = builtins.filter (
+    name:
+    let
+      result = builtins.tryEval (
+        lib.isDerivation (lib.attrByPath (lib.splitString "." name) null pkgs)
+      );
+    in
+    !(result.success && result.value)
   );
 }
@@ -0,0 +1,27 @@
+{ lib }:
+
+# Get metadata for the packages in a profile, for `npingler list`.
+paths:
+
+map (
+  drv:
+  let
+    licenses = lib.toList (drv.meta.license or [ ]);
+    string = value: if builtins.isString value then value else null;
+  in
+  {
+    name = drv.name or null;
+    pname = drv.pname or null;
+    version = drv.version or null;
+    outputs = drv.outputs or [ "out" ];
+    description = string (drv.meta.description or null);
+    homepage = string (drv.meta.homepage or null);
+    licenses = map (
+      license:
+      if builtins.isString license then
+        license
+      else
+        license.spdxId or license.shortName or license.fullName or "unknown"
+    ) licenses;
+  }
+) (builtins.filter lib.isDerivation paths)
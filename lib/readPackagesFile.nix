@@ -0,0 +1,22 @@
+{ lib, pkgs }:
+
+# Read the `nixpkgs` attribute paths listed in a `packages.toml` file, like:
+#
+#     # Installed on every host.
+#     packages = [ "ripgrep" ]
+#
+#     [host.grandiflora]
+#     packages = [ "gdb" ]
+{
+  file,
+  # The `npingler.<host>` attribute whose `[host.<host>]` packages to include.
+  host ? null,
+}:
+
+let
+  contents = builtins.fromTOML (builtins.readFile file);
+  names =
+    (contents.packages or [ ])
+    ++ lib.optionals (host != null) (lib.attrByPath [ "host" host "packages" ] [ ] contents);
+in
+map (name: lib.getAttrFromPath (lib.splitString "." name) pkgs) names
@@ -1,32 +1,83 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::process::Command;
+use std::sync::LazyLock;
 
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
 use clap::CommandFactory;
 use command_error::CommandExt;
 use command_error::Utf8ProgramAndArgs;
+use itertools::Itertools;
 use miette::Context;
 use miette::IntoDiagnostic;
 use miette::miette;
 use owo_colors::OwoColorize;
+use regex::Regex;
 use serde::de::DeserializeOwned;
 use tracing::instrument;
 
+use crate::build_failure::BuildFailure;
+use crate::bundle;
+use crate::bundle::Manifest;
 use crate::cli;
 use crate::cli::Args;
+use crate::collisions::BuildEnv;
+use crate::collisions::Collisions;
 use crate::config::Config;
+use crate::doctor::Doctor;
+use crate::eval_error::EvalFailure;
 use crate::format_bulleted_list;
+use crate::fs::copy_dir_all;
 use crate::fs::resolve_symlink_utf8;
+use crate::hosts::HostNotFound;
+use crate::init::Init;
+use crate::nix::BuildError;
+use crate::nix::Derivation;
+use crate::nix::EvalError;
 use crate::nix::Nix;
 use crate::nix::Registry;
+use crate::nix::drv_name;
+use crate::nix::missing_paths;
+use crate::nix::parse_name;
+use crate::package_list::PackageInfo;
+use crate::package_list::group_packages;
+use crate::packages_file::PACKAGES_FILE;
+use crate::packages_file::PackagesFile;
 use crate::pins::NixPins;
+use crate::privilege::Privilege;
+use crate::privilege::PrivilegeKeepAlive;
+use crate::profiles::BASE_PROFILE;
+use crate::profiles::ManagedProfile;
+use crate::profiles::ProfileNotFound;
+use crate::update_preview::npins_update_command;
+use crate::update_preview::package_versions;
+use crate::update_preview::pin_versions;
+use crate::update_preview::print_changes;
+
+/// The attribute used when there's no `npingler.<host>` attribute for the current host.
+const DEFAULT_HOST: &str = "default";
+
+/// Matches a `nixpkgs` attribute path, like `python3Packages.black`.
+static ATTR_PATH: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^[A-Za-z_][A-Za-z0-9_'-]*(\.[A-Za-z_][A-Za-z0-9_'-]*)*$").expect("Regex is valid")
+});
 
 pub struct App {
     pub config: Config,
-    nix_file: Utf8PathBuf,
+    /// The `npingler` Nix file, which may be missing when switching from a bundle.
+    nix_file: Option<Utf8PathBuf>,
     nix_profile: Utf8PathBuf,
     hostname: String,
+    /// The `npingler.<host>` attribute to use, which may differ from `hostname`.
+    ///
+    /// This changes to `default` if the host turns out to be missing; see
+    /// [`App::with_host_fallback`].
+    host: RefCell<String>,
     nix: Nix,
+    privilege: Privilege,
+    /// Store paths to switch to instead of evaluating the Nix file.
+    manifest: Option<Manifest>,
 }
 
 impl App {
@@ -46,6 +97,54 @@ impl App {
             cli::Command::Config(config_command) => {
                 match config_command {
                     cli::ConfigCommand::Init { output } => Config::init(output.as_deref())?,
+                    cli::ConfigCommand::Show { .. } => {
+                        let config = Config::from_args(args.clone())?;
+                        print!("{}", config.show());
+                    }
+                    cli::ConfigCommand::Migrate => {
+                        Config::from_args(args.clone())?.migrate()?;
+                    }
+                }
+                return Ok(());
+            }
+            cli::Command::Doctor { .. } => {
+                // Diagnose broken configuration files rather than failing to start.
+                let (config, load_error) = Config::from_args_lenient(args.clone())?;
+                crate::tracing::update_log_filters(&filter_reload, &config.log_filter())?;
+                return Doctor::new(&config, load_error).run();
+            }
+            cli::Command::Init {
+                directory,
+                import_profile,
+                no_build,
+                nixpkgs_channel,
+                npingler_repo,
+                ..
+            } => {
+                let config = Config::from_args(args.clone())?;
+                crate::tracing::update_log_filters(&filter_reload, &config.log_filter())?;
+                let nix_file = Init::new(
+                    &config,
+                    directory.as_deref(),
+                    *import_profile,
+                    nixpkgs_channel,
+                    npingler_repo,
+                )?
+                .run()?;
+
+                if *no_build {
+                    return Ok(());
+                }
+                match config.run_mode() {
+                    crate::config::RunMode::Dry => {
+                        tracing::info!("Would build {nix_file}");
+                    }
+                    crate::config::RunMode::Wet => {
+                        let mut args = args.clone();
+                        args.file = Some(nix_file.to_string());
+                        App::from_args(args)?.build_packages()?;
+                        tracing::info!("Created {nix_file}; run `npingler switch` to switch to it");
+                    }
                 }
                 return Ok(());
             }
@@ -86,21 +185,80 @@ impl App {
                 // TODO: Avoid duplicate evals!
 
                 match app.command() {
+                    cli::Command::Update { preview: true, .. } => {
+                        app.preview_update()?;
+                    }
                     cli::Command::Update { no_switch, .. } => {
+                        let _privileges = if *no_switch {
+                            None
+                        } else {
+                            app.acquire_privileges()?
+                        };
                         app.update()?;
                         if !no_switch {
                             app.switch()?;
                         }
                     }
                     cli::Command::Switch { .. } => {
+                        let _privileges = app.acquire_privileges()?;
                         app.switch()?;
                     }
                     cli::Command::Build { .. } => {
                         app.build_packages()?;
                     }
+                    cli::Command::Bundle { output, .. } => {
+                        app.bundle(output)?;
+                    }
+                    cli::Command::Add {
+                        packages,
+                        shared,
+                        switch,
+                        ..
+                    } => {
+                        if app.add_packages(packages, *shared)? && *switch {
+                            app.switch_after_edit()?;
+                        }
+                    }
+                    cli::Command::Remove {
+                        packages,
+                        shared,
+                        switch,
+                        ..
+                    } => {
+                        if app.remove_packages(packages, *shared)? && *switch {
+                            app.switch_after_edit()?;
+                        }
+                    }
+                    cli::Command::Search { terms, pin, .. } => {
+                        app.search(terms, pin)?;
+                    }
+                    cli::Command::List {
+                        filter,
+                        json,
+                        no_eval,
+                        ..
+                    } => {
+                        app.list(filter.as_deref(), *json, *no_eval)?;
+                    }
+                    cli::Command::Which { command, .. } => {
+                        app.which(command)?;
+                    }
+                    cli::Command::Status { .. } => {
+                        app.status()?;
+                    }
+                    cli::Command::Hosts { .. } => {
+                        app.list_hosts()?;
+                    }
+                    cli::Command::Profiles(cli::ProfilesCommand::List { .. }) => {
+                        app.list_profiles()?;
+                    }
                     cli::Command::Config(config_command) => match config_command {
                         cli::ConfigCommand::Init { .. } => unreachable!(),
+                        cli::ConfigCommand::Show { .. } => unreachable!(),
+                        cli::ConfigCommand::Migrate => unreachable!(),
                     },
+                    cli::Command::Doctor { .. } => unreachable!(),
+                    cli::Command::Init { .. } => unreachable!(),
                     cli::Command::Util(util_command) => match util_command {
                         cli::UtilCommand::GenerateCompletions { .. } => unreachable!(),
                         #[cfg(feature = "clap_mangen")]
@@ -116,41 +274,250 @@ impl App {
     pub fn from_args(args: Args) -> miette::Result<Self> {
         let config = Config::from_args(args)?;
         let nix = config.nix()?;
-        let nix_file = config.nix_file()?;
+        let hostname = config.hostname()?;
+        let host = config
+            .host_alias(&hostname)?
+            .unwrap_or(&hostname)
+            .to_owned();
+        let manifest = match (config.bundle(), config.store_path()) {
+            (Some(bundle), _) => Some(Manifest::read(bundle)?),
+            (None, Some(store_path)) => {
+                let profile_name = match config.profile_names() {
+                    [] => BASE_PROFILE,
+                    [name] => name.as_str(),
+                    _ => {
+                        return Err(miette!(
+                            "`--store-path` can only switch one profile, but multiple \
+                            `--profile-name`s were given"
+                        ));
+                    }
+                };
+                Some(Manifest::from_store_path(
+                    &host,
+                    profile_name,
+                    store_path,
+                    config.pins_file(),
+                )?)
+            }
+            (None, None) => None,
+        };
+        let nix_file = if manifest.is_some() {
+            config
+                .nix_file()
+                .inspect_err(|err| tracing::debug!("No Nix file, using prebuilt paths: {err}"))
+                .ok()
+        } else {
+            Some(config.nix_file()?)
+        };
         // TODO: Should we create this profile if it doesn't exist?
         let nix_profile = config.nix_profile(&nix)?;
-        let hostname = config.hostname()?;
-        ::tracing::debug!(%nix_file, ?nix_profile, %hostname, "Resolved configuration");
-        Ok(Self {
+        let host = match &manifest {
+            Some(manifest) => manifest.host.clone(),
+            None => host,
+        };
+        let privilege = config.privilege();
+        let app = Self {
             config,
             nix_file,
             nix_profile,
             nix,
             hostname,
-        })
+            host: RefCell::new(host),
+            privilege,
+            manifest,
+        };
+        ::tracing::debug!(
+            nix_file = ?app.nix_file,
+            nix_profile = ?app.nix_profile,
+            hostname = %app.hostname,
+            host = %app.host(),
+            privilege = ?app.privilege,
+            "Resolved configuration"
+        );
+        Ok(app)
+    }
+
+    /// The `npingler.<host>` attribute to use.
+    fn host(&self) -> String {
+        self.host.borrow().clone()
+    }
+
+    /// The host to use if the current host is missing from `hosts`, if any.
+    fn fallback_host(&self, hosts: &[String]) -> Option<&'static str> {
+        let host = self.host();
+        (self.manifest.is_none()
+            && host != DEFAULT_HOST
+            && !hosts.contains(&host)
+            && hosts.iter().any(|host| host == DEFAULT_HOST))
+        .then_some(DEFAULT_HOST)
+    }
+
+    /// Use `npingler.default` if there's no attribute for the current host.
+    ///
+    /// Returns `true` if the host changed.
+    fn use_fallback_host(&self) -> bool {
+        let hosts = match self.hosts() {
+            Ok(hosts) => hosts,
+            Err(err) => {
+                tracing::debug!("Failed to list hosts:\n{err:?}");
+                return false;
+            }
+        };
+        match self.fallback_host(&hosts) {
+            Some(fallback) => {
+                tracing::info!(
+                    "No configuration for host `{}`, using `npingler.{fallback}`",
+                    self.host()
+                );
+                *self.host.borrow_mut() = fallback.to_owned();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Evaluate or build something in `npingler.<host>`, retrying with `npingler.default` if
+    /// that fails because the host is missing.
+    ///
+    /// Checking for the host up front would cost an extra evaluation on every run.
+    fn with_host_fallback<T>(&self, f: impl Fn() -> miette::Result<T>) -> miette::Result<T> {
+        match f() {
+            Ok(value) => Ok(value),
+            Err(err) => {
+                if self.use_fallback_host() {
+                    f().map_err(|err| self.explain_missing_host(err))
+                } else {
+                    Err(self.explain_missing_host(err))
+                }
+            }
+        }
     }
 
     pub fn command(&self) -> &crate::cli::Command {
         self.config.command()
     }
 
+    /// If we'll need `root` privileges later, get them now so that we don't prompt for a
+    /// password after a long build.
+    pub fn acquire_privileges(&self) -> miette::Result<Option<PrivilegeKeepAlive>> {
+        if !self.config.registry_pin_root()
+            && !self.config.channels_pin_root()
+            && !self.config.system_profile()
+        {
+            return Ok(None);
+        }
+
+        match self.config.run_mode() {
+            crate::config::RunMode::Dry => Ok(None),
+            crate::config::RunMode::Wet => self.privilege.acquire(),
+        }
+    }
+
+    fn nix_file(&self) -> miette::Result<&Utf8Path> {
+        self.nix_file
+            .as_deref()
+            .ok_or_else(|| miette!("No `npingler` Nix file found"))
+    }
+
     fn npingler_attr(&self, attr: &str) -> String {
-        format!("npingler.{}.{}", self.hostname, attr)
+        format!("npingler.{}.{}", self.host(), attr)
+    }
+
+    /// Get the names of the hosts in the `npingler` attribute set.
+    #[instrument(level = "debug", skip(self))]
+    pub fn hosts(&self) -> miette::Result<Vec<String>> {
+        self.nix.eval(&[
+            "--file",
+            self.nix_file()?.as_str(),
+            "--apply",
+            "builtins.attrNames",
+            "npingler",
+        ])
+    }
+
+    /// Print the hosts in the `npingler` attribute set, marking the current host.
+    pub fn list_hosts(&self) -> miette::Result<()> {
+        let hosts = self.hosts()?;
+        let current = self
+            .fallback_host(&hosts)
+            .map(str::to_owned)
+            .unwrap_or_else(|| self.host());
+        for host in hosts {
+            if host == current {
+                println!("{} {}", "*".green(), host.bold());
+            } else {
+                println!("  {host}");
+            }
+        }
+        Ok(())
+    }
+
+    /// If `err` was caused by the current host missing from the `npingler` attribute set, replace
+    /// it with a more helpful error.
+    fn explain_missing_host(&self, err: miette::Report) -> miette::Report {
+        let host = self.host();
+        match self.hosts() {
+            Ok(hosts) if !hosts.contains(&host) => HostNotFound::new(
+                &self.hostname,
+                &host,
+                self.config.hostname_source(),
+                self.nix_file.clone().unwrap_or_default(),
+                &hosts,
+            )
+            .into(),
+            Ok(_) => err,
+            Err(hosts_err) => {
+                tracing::debug!("Failed to list hosts:\n{hosts_err:?}");
+                err
+            }
+        }
+    }
+
+    /// If `err` was caused by Nix failing to evaluate an expression, replace it with a diagnostic
+    /// pointing at the error in the user's configuration.
+    fn explain_eval_error(&self, err: miette::Report) -> miette::Report {
+        let Some(directory) = self.nix_file.as_deref().and_then(Utf8Path::parent) else {
+            return err;
+        };
+        match err.downcast_ref::<EvalError>().and_then(|eval_error| {
+            EvalFailure::parse(&eval_error.stderr, directory, self.config.verbose())
+        }) {
+            Some(failure) => failure.into(),
+            None => err,
+        }
+    }
+
+    /// If `err` was caused by derivations failing to build, replace it with a diagnostic naming
+    /// them, showing why `root` depends on them, and saving their logs.
+    fn explain_build_failure(
+        &self,
+        err: miette::Report,
+        root: Option<&Utf8Path>,
+    ) -> miette::Report {
+        match err.downcast::<BuildError>() {
+            Ok(err) => BuildFailure::new(&self.nix, self.config.project_paths(), err, root).into(),
+            Err(err) => err,
+        }
     }
 
     #[instrument(level = "debug", skip(self))]
     fn build_npingler_attr(&self, attr: &str) -> miette::Result<Utf8PathBuf> {
+        let nix_file = self.nix_file()?;
+        let out_paths = self
+            .with_host_fallback(|| {
+                self.nix
+                    .build(&["--file", nix_file.as_str(), &self.npingler_attr(attr)])
+                    .map_err(|err| self.explain_build_failure(err, None))
+            })
+            .map_err(|err| self.explain_eval_error(err))?;
         let attr = self.npingler_attr(attr);
-        let out_paths = self.nix.build(&["--file", self.nix_file.as_str(), &attr])?;
         if out_paths.is_empty() {
             Err(miette!(
-                "Building attr {attr} from {} produced no paths",
-                self.nix_file
+                "Building attr {attr} from {nix_file} produced no paths"
             ))
         } else if out_paths.len() > 1 {
             Err(miette!(
-                "Building attr {attr} from {} produced too many paths:\n{}",
-                self.nix_file,
+                "Building attr {attr} from {nix_file} produced too many paths:\n{}",
                 format_bulleted_list(&out_paths)
             ))
         } else {
@@ -166,33 +533,30 @@ impl App {
     where
         T: DeserializeOwned,
     {
-        let attr = self.npingler_attr(attr);
-        let mut args = vec!["--file", &self.nix_file.as_str()];
+        let nix_file = self.nix_file()?;
+        self.with_host_fallback(|| {
+            let attr = self.npingler_attr(attr);
+            let mut args = vec!["--file", nix_file.as_str()];
 
-        if let Some(expr) = apply {
-            args.push("--apply");
-            args.push(expr);
-        }
+            if let Some(expr) = apply {
+                args.push("--apply");
+                args.push(expr);
+            }
 
-        args.push(&attr);
+            args.push(&attr);
 
-        self.nix.eval(&args)
+            self.nix.eval(&args)
+        })
+        .map_err(|err| self.explain_eval_error(err))
     }
 
     #[instrument(level = "debug", skip(self))]
     pub fn update(&self) -> miette::Result<()> {
-        let directory = self
-            .nix_file
-            .parent()
-            .ok_or_else(|| miette!("Nix file has no parent directory: {}", self.nix_file))?;
+        let directory = self.config_directory()?;
 
         tracing::info!(%directory, "Upgrading `npins`");
 
-        let mut command = Command::new("npins");
-        command.current_dir(directory);
-        command.arg("update");
-        // TODO: Only run `npins` in verbose mode if `npingler` is in verbose mode?
-        command.arg("--verbose");
+        let mut command = npins_update_command(directory);
 
         match self.config.run_mode() {
             crate::config::RunMode::Dry => {
@@ -208,17 +572,317 @@ impl App {
         Ok(())
     }
 
-    fn get_profile_store_path(&self) -> miette::Result<Utf8PathBuf> {
-        resolve_symlink_utf8(self.nix_profile.clone())
+    /// The directory containing the Nix file and its `npins` directory.
+    fn config_directory(&self) -> miette::Result<&Utf8Path> {
+        let nix_file = self.nix_file()?;
+        nix_file
+            .parent()
+            .ok_or_else(|| miette!("Nix file has no parent directory: {nix_file}"))
+    }
+
+    /// Update the `npins` in a copy of the configuration directory and show how the pins and
+    /// packages would change, without touching the real `npins/sources.json` or profiles.
+    #[instrument(level = "debug", skip(self))]
+    pub fn preview_update(&self) -> miette::Result<()> {
+        let nix_file = self.nix_file()?;
+        let directory = self.config_directory()?;
+
+        let temp_dir = tempfile::Builder::new()
+            .prefix("npingler-preview-")
+            .tempdir()
+            .into_diagnostic()
+            .wrap_err("Failed to create temporary directory")?;
+        let preview_directory = Utf8Path::from_path(temp_dir.path())
+            .ok_or_else(|| miette!("Temporary directory is not UTF-8: {:?}", temp_dir.path()))?;
+        copy_dir_all(directory, preview_directory)?;
+
+        tracing::info!(%directory, "Upgrading `npins` in a copy of the configuration");
+        npins_update_command(preview_directory)
+            .status_checked()
+            .wrap_err_with(|| format!("Failed to upgrade `npins` in {preview_directory}"))?;
+
+        let sources = Utf8Path::new("npins").join("sources.json");
+        let old_pins = crate::npins::read_sources(&directory.join(&sources))?;
+        let new_pins = crate::npins::read_sources(&preview_directory.join(&sources))?;
+        print_changes("Pins", &pin_versions(&old_pins), &pin_versions(&new_pins));
+
+        let preview_nix_file = preview_directory.join(
+            nix_file
+                .strip_prefix(directory)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("{nix_file} is not in {directory}"))?,
+        );
+        for profile in self.profiles()? {
+            let old = if profile.link.exists() {
+                let profile_path = resolve_symlink_utf8(profile.link.clone())?;
+                let references = self
+                    .nix
+                    .path_info(&profile_path)?
+                    .references
+                    .into_iter()
+                    .filter(|path| *path != profile_path)
+                    .collect();
+                group_packages(&profile.name, references, Vec::new())
+            } else {
+                Vec::new()
+            };
+            let new = self
+                .eval_package_infos(&preview_nix_file, &profile)?
+                .ok_or_else(|| {
+                    miette!("The updated `npingler` pin doesn't provide package metadata")
+                })?;
+
+            print_changes(
+                &format!("Packages in the {}", profile.description()),
+                &package_versions(old.iter().map(|package| package.name.as_str())),
+                &package_versions(new.iter().filter_map(PackageInfo::name)),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Read `packages.toml`, next to the Nix file.
+    fn packages_file(&self) -> miette::Result<PackagesFile> {
+        PackagesFile::read(self.config_directory()?)
+    }
+
+    /// Add packages to `packages.toml`, checking that they exist first.
+    ///
+    /// Returns `true` if the file changed.
+    pub fn add_packages(&self, packages: &[String], shared: bool) -> miette::Result<bool> {
+        self.check_packages_exist(packages)?;
+
+        let host = self.host();
+        let host = (!shared).then_some(host.as_str());
+        let mut file = self.packages_file()?;
+        let existed = file.path().exists();
+        for package in packages {
+            if !file.add(host, package)? {
+                tracing::info!(
+                    "`{package}` is already in the {}",
+                    PackagesFile::list_name(host)
+                );
+            }
+        }
+        let changed = file.write(&self.config.run_mode())?;
+
+        if !existed {
+            self.check_packages_file_used(file.path())?;
+        }
+        Ok(changed)
+    }
+
+    /// Remove packages from `packages.toml`.
+    ///
+    /// Returns `true` if the file changed.
+    pub fn remove_packages(&self, packages: &[String], shared: bool) -> miette::Result<bool> {
+        // Nothing has been evaluated yet, so check if the host needs to fall back to the default.
+        if !shared {
+            self.use_fallback_host();
+        }
+        let current = self.host();
+        let host = (!shared).then_some(current.as_str());
+        let other = shared.then_some(current.as_str());
+        let mut file = self.packages_file()?;
+        for package in packages {
+            if !file.remove(host, package)? {
+                if file.packages(other).contains(package) {
+                    tracing::warn!(
+                        "`{package}` isn't in the {}, but it's in the {}; {} `--shared` to \
+                        remove it",
+                        PackagesFile::list_name(host),
+                        PackagesFile::list_name(other),
+                        if shared { "omit" } else { "use" },
+                    );
+                } else {
+                    tracing::warn!("`{package}` isn't in the {}", PackagesFile::list_name(host));
+                }
+            }
+        }
+        file.write(&self.config.run_mode())
+    }
+
+    /// Check that packages exist in the `nixpkgs` used by the current host's profile.
+    fn check_packages_exist(&self, packages: &[String]) -> miette::Result<()> {
+        if let Some(package) = packages.iter().find(|package| !ATTR_PATH.is_match(package)) {
+            return Err(miette!(
+                "`{package}` is not a valid attribute path, like `ripgrep` or \
+                `python3Packages.black`"
+            ));
+        }
+
+        let names = packages
+            .iter()
+            .map(|package| format!("\"{package}\""))
+            .join(" ");
+        let nix_file = self.nix_file()?;
+        let missing: Option<Vec<String>> = self
+            .with_host_fallback(|| {
+                self.nix.eval(&[
+                    "--file",
+                    nix_file.as_str(),
+                    "--apply",
+                    &format!(
+                        "host: if host ? missingPackages then host.missingPackages [ {names} ] else null"
+                    ),
+                    &format!("npingler.{}", self.host()),
+                ])
+            })
+            .map_err(|err| self.explain_eval_error(err))?;
+
+        match missing {
+            None => {
+                tracing::warn!(
+                    "Can't check that the packages exist because the pinned `npingler` is too \
+                    old; run `npingler update` to update it"
+                );
+                Ok(())
+            }
+            Some(missing) if !missing.is_empty() => Err(miette!(
+                "Packages not found in the pinned `nixpkgs`:\n{}",
+                format_bulleted_list(missing.iter().map(|package| format!("`{package}`")))
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Warn if the Nix file doesn't appear to use `packages.toml`.
+    fn check_packages_file_used(&self, packages_file: &Utf8Path) -> miette::Result<()> {
+        let nix_file = self.nix_file()?;
+        let contents = fs_err::read_to_string(nix_file).into_diagnostic()?;
+        if !contents.contains(PACKAGES_FILE) {
+            tracing::warn!(
+                "{nix_file} doesn't mention `{PACKAGES_FILE}`, so the packages in \
+                {packages_file} won't be installed. Add this to `npingler.{}`'s `makeProfile` \
+                arguments:\n    packagesFile = ./{PACKAGES_FILE};\n    host = {};",
+                self.host(),
+                serde_json::to_string(&self.host()).expect("Strings serialize to JSON"),
+            );
+        }
+        Ok(())
+    }
+
+    /// Switch to the profile after `add` or `remove` changed `packages.toml`.
+    pub fn switch_after_edit(&self) -> miette::Result<()> {
+        match self.config.run_mode() {
+            crate::config::RunMode::Dry => {
+                tracing::info!("Would switch to the updated profile");
+                Ok(())
+            }
+            crate::config::RunMode::Wet => {
+                let _privileges = self.acquire_privileges()?;
+                self.switch()
+            }
+        }
     }
 
+    /// Get the profiles defined for the current host, in order.
+    ///
+    /// The base profile (`npingler.<host>.packages`) comes first, followed by the profiles in
+    /// `npingler.<host>.profiles`.
     #[instrument(level = "debug", skip(self))]
-    pub fn build_packages(&self) -> miette::Result<(Option<Utf8PathBuf>, Utf8PathBuf)> {
-        tracing::info!("Building profile packages");
+    pub fn all_profiles(&self) -> miette::Result<Vec<ManagedProfile>> {
+        if let Some(manifest) = &self.manifest {
+            return manifest
+                .profiles
+                .keys()
+                .map(|name| self.managed_profile(name))
+                .collect();
+        }
+
+        let nix_file = self.nix_file()?;
+        let names: Vec<String> = self
+            .with_host_fallback(|| {
+                self.nix.eval(&[
+                    "--file",
+                    nix_file.as_str(),
+                    "--apply",
+                    "host: builtins.attrNames (host.profiles or { })",
+                    &format!("npingler.{}", self.host()),
+                ])
+            })
+            .map_err(|err| self.explain_eval_error(err))?;
+
+        let mut profiles = vec![self.managed_profile(BASE_PROFILE)?];
+        for name in names {
+            if name == BASE_PROFILE {
+                return Err(miette!(
+                    "`npingler.{}.profiles.{BASE_PROFILE}` conflicts with the base profile; \
+                    use `npingler.{}.packages` or choose a different name",
+                    self.host(),
+                    self.host()
+                ));
+            }
+            profiles.push(self.managed_profile(&name)?);
+        }
+        Ok(profiles)
+    }
+
+    fn managed_profile(&self, name: &str) -> miette::Result<ManagedProfile> {
+        if name == BASE_PROFILE {
+            Ok(ManagedProfile::base(self.nix_profile.clone()))
+        } else {
+            let link = self.config.named_nix_profile(name)?;
+            Ok(ManagedProfile::named(name, link))
+        }
+    }
+
+    /// Get the profiles selected with `--profile-name`, or all profiles if none were selected.
+    pub fn profiles(&self) -> miette::Result<Vec<ManagedProfile>> {
+        let profiles = self.all_profiles()?;
+        let names = self.config.profile_names();
+        if names.is_empty() {
+            return Ok(profiles);
+        }
+
+        names
+            .iter()
+            .map(|name| {
+                profiles
+                    .iter()
+                    .find(|profile| &profile.name == name)
+                    .cloned()
+                    .ok_or_else(|| ProfileNotFound::new(name, &self.host(), &profiles).into())
+            })
+            .collect()
+    }
 
-        let old_profile = self
-            .get_profile_store_path()
-            .inspect_err(|err| tracing::debug!("Failed to resolve {:?}: {err}", self.nix_profile))
+    /// Print the profiles defined for the current host and their profile links.
+    pub fn list_profiles(&self) -> miette::Result<()> {
+        for profile in self.all_profiles()? {
+            let state = match resolve_symlink_utf8(profile.link.clone()) {
+                Ok(path) => path.to_string(),
+                Err(_) => "not switched yet".to_owned(),
+            };
+            println!(
+                "{} {} {}",
+                profile.name.bold(),
+                profile.link,
+                format!("({state})").dimmed()
+            );
+        }
+        Ok(())
+    }
+
+    /// Build all the selected profiles.
+    pub fn build_packages(&self) -> miette::Result<()> {
+        for profile in self.profiles()? {
+            self.build_profile(&profile)?;
+        }
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    pub fn build_profile(
+        &self,
+        profile: &ManagedProfile,
+    ) -> miette::Result<(Option<Utf8PathBuf>, Utf8PathBuf)> {
+        let description = profile.description();
+        tracing::info!("Building {description} packages");
+
+        let old_profile = resolve_symlink_utf8(profile.link.clone())
+            .inspect_err(|err| tracing::debug!("Failed to resolve {:?}: {err}", profile.link))
             .inspect(|old_profile| tracing::debug!(?old_profile, "Resolved Nix profile"))
             .ok();
         tracing::debug!(?old_profile, "Resolved old profile");
@@ -238,11 +902,37 @@ impl App {
             tracing::info!(
                 out=%old_profile,
                 drv=old_profile_drv.as_ref().map(|drv| drv.path.as_str()).unwrap_or("<unknown>"),
-                "Resolved current profile"
+                "Resolved current {description}"
             );
         }
 
-        let new_profile: Utf8PathBuf = self.eval_npingler_attr("packages", None)?;
+        let new_profile = match &self.manifest {
+            Some(manifest) => self.prebuilt_profile(manifest, profile)?,
+            None => self.build_evaluated_profile(profile, old_profile_drv.as_ref())?,
+        };
+
+        if old_profile.as_deref() == Some(new_profile.as_path()) {
+            tracing::info!("No changes, {description} already up to date");
+        } else if let Err(err) = self.diff_trees(old_profile.as_deref(), new_profile.as_path()) {
+            let old_profile = old_profile
+                .clone()
+                .map(|path| path.as_str().to_owned())
+                .unwrap_or_default();
+            tracing::debug!("Failed to diff profiles {old_profile} -> {new_profile}:\n{err}");
+        }
+
+        Ok((old_profile, new_profile))
+    }
+
+    /// Evaluate and build a profile, returning its store path.
+    fn build_evaluated_profile(
+        &self,
+        profile: &ManagedProfile,
+        old_profile_drv: Option<&Derivation>,
+    ) -> miette::Result<Utf8PathBuf> {
+        let description = profile.description();
+
+        let new_profile: Utf8PathBuf = self.eval_npingler_attr(&profile.attr, None)?;
         tracing::debug!(?new_profile, "Resolved new profile");
         let new_profile_drv = self.nix.derivation_info(&new_profile)?;
         tracing::debug!(?new_profile_drv, "Resolved new profile .drv");
@@ -250,12 +940,12 @@ impl App {
         tracing::info!(
             out=%new_profile,
             drv=%new_profile_drv.path,
-            "Resolved new profile"
+            "Resolved new {description}"
         );
 
         if let Some(diff_derivations_command) = self.config.diff_derivations()?
             && let Some(command) = diff_derivations_command.first()
-            && let Some(old_profile_drv) = &old_profile_drv
+            && let Some(old_profile_drv) = old_profile_drv
             && old_profile_drv != &new_profile_drv
         {
             // Don't care... but use `status_checked` anyways to get logs :)
@@ -271,31 +961,486 @@ impl App {
         }
 
         if new_profile.exists() {
-            tracing::info!("New profile is already built");
+            tracing::info!("New {description} is already built");
         } else {
+            if self.nix.offline() {
+                self.check_offline_buildable(&new_profile_drv, &description)?;
+            }
+
             match self.config.run_mode() {
                 crate::config::RunMode::Dry => {
+                    self.check_collisions(&new_profile_drv, &description)?;
                     tracing::info!("Would build: {new_profile} from {}", new_profile_drv.path);
                 }
                 crate::config::RunMode::Wet => {
+                    self.check_collisions(&new_profile_drv, &description)?;
                     self.nix
                         .build(&[&format!("{}^out", new_profile_drv.path.as_str())])
-                        .wrap_err("Failed to build new profile")?;
+                        .map_err(|err| self.explain_build_failure(err, Some(&new_profile_drv.path)))
+                        .wrap_err_with(|| format!("Failed to build new {description}"))?;
                 }
             }
         }
 
-        if old_profile.as_deref() == Some(new_profile.as_path()) {
-            tracing::info!("No changes, profile already up to date");
-        } else if let Err(err) = self.diff_trees(old_profile.as_deref(), new_profile.as_path()) {
-            let old_profile = old_profile
-                .clone()
-                .map(|path| path.as_str().to_owned())
+        Ok(new_profile)
+    }
+
+    /// Build the packages in a profile and check them for file collisions, so that they can be
+    /// explained before `buildEnv` fails (or silently picks one package).
+    ///
+    /// Dry runs don't build anything, so they only check profiles whose packages are already
+    /// built.
+    fn check_collisions(&self, drv: &Derivation, description: &str) -> miette::Result<()> {
+        let Some(build_env) = BuildEnv::from_derivation(drv) else {
+            tracing::debug!(drv = %drv.path, "Not a `buildEnv`, skipping collision checks");
+            return Ok(());
+        };
+
+        if build_env.is_built() || drv.input_drvs.is_empty() {
+            return self.report_collisions(&build_env, description);
+        }
+
+        if matches!(self.config.run_mode(), crate::config::RunMode::Dry) {
+            tracing::info!(
+                "Not checking the new {description} for collisions, because its packages aren't \
+                 built yet"
+            );
+            return Ok(());
+        }
+
+        // Only build the linked packages, not the rest of the `buildEnv`'s inputs.
+        let inputs = self
+            .nix
+            .derivation_infos(drv.input_drvs.keys().map(Utf8PathBuf::as_path))?;
+        let inputs = build_env.installables(&inputs);
+        if !inputs.is_empty() {
+            self.nix
+                .build(&inputs.iter().map(String::as_str).collect::<Vec<_>>())
+                .map_err(|err| self.explain_build_failure(err, Some(&drv.path)))
+                .wrap_err_with(|| {
+                    format!("Failed to build the packages in the new {description}")
+                })?;
+        }
+
+        self.report_collisions(&build_env, description)
+    }
+
+    /// Fail (or warn, if `buildEnv` resolves them) if `build_env`'s packages collide.
+    fn report_collisions(&self, build_env: &BuildEnv, description: &str) -> miette::Result<()> {
+        let collisions = build_env.collisions()?;
+        if collisions.is_empty() {
+            tracing::debug!("No collisions in the new {description}");
+            return Ok(());
+        }
+
+        let fatal =
+            self.config.strict_collisions() || collisions.iter().any(|collision| !collision.silent);
+        let report = miette::Report::new(Collisions::new(description.to_owned(), collisions));
+        if fatal {
+            Err(report)
+        } else {
+            tracing::warn!("{report:?}");
+            Ok(())
+        }
+    }
+
+    /// Fail early if building `drv` would need to download anything.
+    fn check_offline_buildable(&self, drv: &Derivation, description: &str) -> miette::Result<()> {
+        let closure = self.nix.derivation_closure(&drv.path)?;
+        let missing = missing_paths(&closure, &drv.path);
+        if missing.is_empty() {
+            tracing::debug!("The new {description} can be built offline");
+            return Ok(());
+        }
+
+        Err(miette!(
+            help = "Build the new {description} while connected to the network, or copy these \
+                paths into the Nix store with `nix copy` or `npingler bundle`",
+            "Can't build the new {description} offline; {} paths are missing from the Nix store:\n{}",
+            missing.len(),
+            format_bulleted_list(&missing)
+        ))
+    }
+
+    /// Get a profile's prebuilt store path from the manifest.
+    fn prebuilt_profile(
+        &self,
+        manifest: &Manifest,
+        profile: &ManagedProfile,
+    ) -> miette::Result<Utf8PathBuf> {
+        let description = profile.description();
+        let new_profile = manifest
+            .profiles
+            .get(&profile.name)
+            .cloned()
+            .ok_or_else(|| miette!("No prebuilt store path for the {description}"))?;
+
+        tracing::info!(out=%new_profile, "Using prebuilt {description}");
+
+        if !new_profile.exists() {
+            match self.config.run_mode() {
+                crate::config::RunMode::Dry => {
+                    tracing::info!("{new_profile} is not in the Nix store yet");
+                }
+                crate::config::RunMode::Wet => {
+                    return Err(miette!("{new_profile} is not in the Nix store"));
+                }
+            }
+        }
+
+        Ok(new_profile)
+    }
+
+    /// Get the pinned sources, from the manifest if switching to prebuilt paths.
+    fn pins(&self) -> miette::Result<NixPins> {
+        match &self.manifest {
+            Some(manifest) => Ok(manifest.pins.clone().unwrap_or_default()),
+            None => self.eval_npingler_attr("pins.pins", None),
+        }
+    }
+
+    /// Search the pinned `pin` source for packages, marking the ones installed in a profile.
+    #[instrument(level = "debug", skip(self))]
+    pub fn search(&self, terms: &[String], pin: &str) -> miette::Result<()> {
+        let pins = self.pins()?;
+        let source = pins.entries.get(pin).ok_or_else(|| {
+            miette!(
+                help = format!(
+                    "Pinned sources for `npingler.{}`: {}",
+                    self.host(),
+                    pins.entries
+                        .keys()
+                        .map(|name| format!("`{name}`"))
+                        .join(", ")
+                ),
+                "`npingler.{}.pins` has no `{pin}` entry",
+                self.host()
+            )
+        })?;
+        tracing::debug!(%source, "Searching pinned source");
+
+        let results = self.nix.search(source, terms)?;
+        if results.is_empty() {
+            tracing::info!("No packages found in `{pin}` ({source})");
+            return Ok(());
+        }
+
+        let installed = self.installed_store_names()?;
+        for (attr, result) in &results {
+            let mut line = format!("* {}", attr.bold());
+            if !result.version.is_empty() {
+                line.push_str(&format!(" ({})", result.version));
+            }
+            if let Some(profiles) = installed.get(&result.store_name()) {
+                line.push_str(&format!(
+                    " {}",
+                    format!("[installed in {}]", profiles.join(", ")).green()
+                ));
+            }
+            println!("{line}");
+            if !result.description.is_empty() {
+                println!("  {}", result.description);
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the store path names (like `ripgrep-14.1.0`) of the packages in the managed profiles,
+    /// mapped to the profiles they're installed in.
+    fn installed_store_names(&self) -> miette::Result<BTreeMap<String, Vec<String>>> {
+        let mut installed: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for profile in self.profiles()? {
+            if !profile.link.exists() {
+                continue;
+            }
+            let path = resolve_symlink_utf8(profile.link.clone())?;
+            let references = self
+                .nix
+                .path_info(&path)
+                .inspect_err(|err| tracing::debug!("Failed to get references of {path}:\n{err}"))
+                .map(|info| info.references)
                 .unwrap_or_default();
-            tracing::debug!("Failed to diff profiles {old_profile} -> {new_profile}:\n{err}");
+            for reference in references {
+                if reference == path {
+                    continue;
+                }
+                let name = drv_name(reference.as_str());
+                installed
+                    .entry(name)
+                    .or_default()
+                    .push(profile.name.clone());
+            }
         }
+        Ok(installed)
+    }
 
-        Ok((old_profile, new_profile))
+    /// List the packages installed in the selected profiles.
+    #[instrument(level = "debug", skip(self))]
+    pub fn list(&self, filter: Option<&str>, json: bool, no_eval: bool) -> miette::Result<()> {
+        let filter = filter
+            .map(|filter| {
+                Regex::new(&format!("(?i){filter}"))
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Invalid filter: {filter}"))
+            })
+            .transpose()?;
+
+        let mut packages = Vec::new();
+        for profile in self.profiles()? {
+            if !profile.link.exists() {
+                tracing::info!(
+                    "The {} hasn't been switched to yet: {}",
+                    profile.description(),
+                    profile.link
+                );
+                continue;
+            }
+            let profile_path = resolve_symlink_utf8(profile.link.clone())?;
+            let references = self
+                .nix
+                .path_info(&profile_path)?
+                .references
+                .into_iter()
+                .filter(|path| *path != profile_path)
+                .collect();
+            let infos = if no_eval || self.manifest.is_some() {
+                Vec::new()
+            } else {
+                self.package_infos(&profile)
+            };
+            packages.extend(
+                group_packages(&profile.name, references, infos)
+                    .into_iter()
+                    .filter(|package| filter.as_ref().is_none_or(|filter| package.matches(filter))),
+            );
+        }
+
+        if json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&packages).into_diagnostic()?
+            );
+            return Ok(());
+        }
+
+        let mut last_profile = None;
+        for package in &packages {
+            if last_profile != Some(&package.profile) {
+                if last_profile.is_some() {
+                    println!();
+                }
+                let profile = self.managed_profile(&package.profile)?;
+                println!("{} ({}):", profile.description().underline(), profile.link);
+                last_profile = Some(&package.profile);
+            }
+            println!("{}", package.display());
+        }
+        Ok(())
+    }
+
+    /// Evaluate metadata for the packages in a profile, or nothing if it can't be evaluated.
+    fn package_infos(&self, profile: &ManagedProfile) -> Vec<PackageInfo> {
+        match self
+            .nix_file()
+            .and_then(|nix_file| self.eval_package_infos(nix_file, profile))
+        {
+            Ok(Some(infos)) => infos,
+            Ok(None) => {
+                tracing::warn!(
+                    "Can't show package metadata because the pinned `npingler` is too old; run \
+                    `npingler update` to update it"
+                );
+                Vec::new()
+            }
+            Err(err) => {
+                tracing::warn!("Failed to evaluate package metadata:\n{err:?}");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Evaluate metadata for the packages in a profile from `nix_file`.
+    ///
+    /// Returns `None` if the pinned `npingler` library doesn't provide metadata.
+    fn eval_package_infos(
+        &self,
+        nix_file: &Utf8Path,
+        profile: &ManagedProfile,
+    ) -> miette::Result<Option<Vec<PackageInfo>>> {
+        self.nix
+            .eval(&[
+                "--file",
+                nix_file.as_str(),
+                "--apply",
+                &format!("host: host.packageInfo.{} or null", profile.attr),
+                &format!("npingler.{}", self.host()),
+            ])
+            .map_err(|err| self.explain_eval_error(err))
+    }
+
+    /// Show which package in the managed profiles provides `command`, and which other packages
+    /// in the same profile provide it too.
+    #[instrument(level = "debug", skip(self))]
+    pub fn which(&self, command: &str) -> miette::Result<()> {
+        if command.contains('/') {
+            return Err(miette!("Expected a command name, not a path: {command}"));
+        }
+
+        let mut found = false;
+        for profile in self.profiles()? {
+            let Some(provider) = self.command_provider(&profile, command)? else {
+                continue;
+            };
+            found = true;
+
+            let name = drv_name(provider.package.as_str());
+            let (pname, version) = parse_name(&name);
+            println!("{} → {}", command.bold(), provider.target);
+            match version {
+                Some(version) => println!("  Package: {pname} {version}"),
+                None => println!("  Package: {pname}"),
+            }
+            println!("  Profile: {} ({})", profile.description(), profile.link);
+            if let Some(deriver) = &provider.deriver {
+                println!("  Derivation: {deriver}");
+            }
+            if !provider.shadowed.is_empty() {
+                println!(
+                    "  Also provided by (not linked into the profile):\n{}",
+                    provider
+                        .shadowed
+                        .iter()
+                        .map(|path| format!("    • {} ({path})", drv_name(path.as_str())))
+                        .join("\n")
+                );
+            }
+        }
+
+        if !found {
+            let on_path = which::which(command)
+                .ok()
+                .and_then(|path| Utf8PathBuf::try_from(path).ok());
+            return Err(match on_path {
+                Some(path) => miette!(
+                    help = format!("`{command}` on your `$PATH` is {path}"),
+                    "`{command}` isn't provided by any `npingler` profile"
+                ),
+                None => miette!("`{command}` isn't provided by any `npingler` profile"),
+            });
+        }
+        Ok(())
+    }
+
+    /// Find the package in a profile which provides `bin/<command>`.
+    fn command_provider(
+        &self,
+        profile: &ManagedProfile,
+        command: &str,
+    ) -> miette::Result<Option<CommandProvider>> {
+        if !profile.link.exists() {
+            return Ok(None);
+        }
+        let profile_path = resolve_symlink_utf8(profile.link.clone())?;
+        let bin = profile_path.join("bin").join(command);
+        if fs_err::symlink_metadata(&bin).is_err() {
+            return Ok(None);
+        }
+
+        // `buildEnv` links `bin` itself into a package if only one package has a `bin`
+        // directory, so resolve the directory too.
+        let target = resolve_symlink_utf8(bin)?;
+        let target = match (target.parent(), target.file_name()) {
+            (Some(parent), Some(file_name)) => {
+                Utf8PathBuf::try_from(fs_err::canonicalize(parent).into_diagnostic()?)
+                    .into_diagnostic()?
+                    .join(file_name)
+            }
+            _ => target,
+        };
+        let package = crate::nix::store_path_root(&target)
+            .ok_or_else(|| miette!("{target} is not in the Nix store"))?;
+
+        let shadowed = self
+            .nix
+            .path_info(&profile_path)?
+            .references
+            .into_iter()
+            .filter(|path| *path != package && *path != profile_path)
+            .filter(|path| fs_err::symlink_metadata(path.join("bin").join(command)).is_ok())
+            .collect();
+        let deriver = self
+            .nix
+            .path_info(&package)
+            .inspect_err(|err| tracing::debug!("Failed to get deriver of {package}:\n{err}"))
+            .ok()
+            .and_then(|info| info.deriver);
+
+        Ok(Some(CommandProvider {
+            target,
+            package,
+            deriver,
+            shadowed,
+        }))
+    }
+
+    /// Build the selected profiles and export them, the channels, and the pinned sources to a
+    /// bundle directory.
+    #[instrument(level = "debug", skip(self))]
+    pub fn bundle(&self, output: &Utf8Path) -> miette::Result<()> {
+        let mut manifest = Manifest::new(&self.host());
+        for profile in self.profiles()? {
+            let (_, new_profile) = self.build_profile(&profile)?;
+            manifest.profiles.insert(profile.name, new_profile);
+        }
+        manifest.pins = Some(self.pins()?);
+        manifest.channels = Some(self.build_npingler_attr("pins.channels")?);
+
+        let mut command = self.nix.copy_command();
+        command.args(["--to", &bundle::store_url(output)?]);
+        command.args(manifest.store_paths());
+
+        match self.config.run_mode() {
+            crate::config::RunMode::Dry => {
+                tracing::info!("Would run: {}", Utf8ProgramAndArgs::from(&command));
+                tracing::info!("Would write bundle manifest to {output}");
+            }
+            crate::config::RunMode::Wet => {
+                tracing::info!("Exporting bundle to {output}");
+                command
+                    .status_checked()
+                    .wrap_err_with(|| format!("Failed to export bundle to {output}"))?;
+                manifest.write(output)?;
+                tracing::info!(
+                    "Wrote bundle to {output}; apply it with `npingler switch --from-bundle {output}`"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Import the store paths recorded in a bundle into the Nix store.
+    #[instrument(level = "debug", skip(self, manifest))]
+    fn import_bundle(&self, bundle: &Utf8Path, manifest: &Manifest) -> miette::Result<()> {
+        tracing::info!("Importing bundle from {bundle}");
+
+        let mut command = self.nix.copy_command();
+        // Bundles aren't signed; we trust them because the user gave us the path.
+        command.args(["--from", &bundle::store_url(bundle)?, "--no-check-sigs"]);
+        command.args(manifest.store_paths());
+
+        match self.config.run_mode() {
+            crate::config::RunMode::Dry => {
+                tracing::info!("Would run: {}", Utf8ProgramAndArgs::from(&command));
+            }
+            crate::config::RunMode::Wet => {
+                command
+                    .status_checked()
+                    .wrap_err_with(|| format!("Failed to import bundle from {bundle}"))?;
+            }
+        }
+
+        Ok(())
     }
 
     fn diff_trees(&self, old: Option<&Utf8Path>, new: &Utf8Path) -> miette::Result<()> {
@@ -325,19 +1470,39 @@ impl App {
 
     #[instrument(level = "debug", skip(self))]
     pub fn ensure_packages(&self) -> miette::Result<()> {
-        let (old_profile, new_profile) = self.build_packages()?;
+        for profile in self.profiles()? {
+            self.ensure_profile(&profile)?;
+        }
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn ensure_profile(&self, profile: &ManagedProfile) -> miette::Result<()> {
+        let (old_profile, new_profile) = self.build_profile(profile)?;
+        let description = profile.description();
+
+        if profile.name == BASE_PROFILE
+            && matches!(self.config.run_mode(), crate::config::RunMode::Wet)
+            && let Err(err) = self.config.create_nix_profile_links(&self.nix)
+        {
+            tracing::warn!("Failed to create Nix profile link: {err}");
+        }
 
         match old_profile {
             Some(old) if old == new_profile => return Ok(()),
             Some(old) => tracing::info!(
-                "Updating profile:\n{}\n{}",
+                "Updating {description}:\n{}\n{}",
                 format!("- {old}").red(),
                 format!("+ {new_profile}").green()
             ),
-            None => tracing::info!("Updating profile:\n{}", format!("+ {new_profile}").green()),
+            None => tracing::info!(
+                "Updating {description}:\n{}",
+                format!("+ {new_profile}").green()
+            ),
         }
 
-        if let Some(profile_dir) = self.nix_profile.parent()
+        if let Some(profile_dir) = profile.link.parent()
+            && !self.config.system_profile()
             && fs_err::symlink_metadata(profile_dir).is_err()
         {
             fs_err::create_dir_all(profile_dir)
@@ -345,9 +1510,10 @@ impl App {
                 .wrap_err("Failed to create missing Nix profile directory")?;
         }
 
-        let mut command = self
-            .nix
-            .nix_env_set_command(&self.nix_profile, &new_profile);
+        let mut command = self.nix.nix_env_set_command(&profile.link, &new_profile);
+        if self.config.system_profile() {
+            command = self.privilege.escalate(command);
+        }
 
         match self.config.run_mode() {
             crate::config::RunMode::Dry => {
@@ -356,7 +1522,7 @@ impl App {
             crate::config::RunMode::Wet => {
                 command
                     .status_checked()
-                    .wrap_err("Failed to install new profile")?;
+                    .wrap_err_with(|| format!("Failed to install new {description}"))?;
             }
         }
 
@@ -389,7 +1555,7 @@ impl App {
         }
 
         let registry = self.config.root_registry_path()?;
-        let mut command = self.nix.sudo_nix_command();
+        let mut command = self.privilege.escalate(self.nix.nix_command());
         command.args([
             "registry",
             "pin",
@@ -425,7 +1591,16 @@ impl App {
 
         let profile = self.config.channels_root_profile()?;
         tracing::debug!(?profile, "Resolved root profile");
-        let channels = self.build_npingler_attr("pins.channels")?;
+        let channels = match &self.manifest {
+            Some(manifest) => match &manifest.channels {
+                Some(channels) => channels.clone(),
+                None => {
+                    tracing::info!("No prebuilt channels, skipping pinning channels");
+                    return Ok(());
+                }
+            },
+            None => self.build_npingler_attr("pins.channels")?,
+        };
         tracing::debug!(?channels, "Built channels");
         let current_channels = fs_err::symlink_metadata(&profile).ok().and_then(|_| {
             crate::fs::resolve_symlink_utf8(profile.clone())
@@ -445,7 +1620,9 @@ impl App {
             tracing::info!("Updating channels:\n- {current_channels}\n+ {channels}");
         }
 
-        let mut command = self.nix.sudo_nix_env_set_command(&profile, &channels);
+        let mut command = self
+            .privilege
+            .escalate(self.nix.nix_env_set_command(&profile, &channels));
 
         match self.config.run_mode() {
             crate::config::RunMode::Dry => {
@@ -468,9 +1645,9 @@ impl App {
             return Ok(());
         }
 
-        let pins: NixPins = self.eval_npingler_attr("pins.pins", None)?;
+        let pins = self.pins()?;
 
-        let registry = match self.nix.parse_registry(Nix::system_registry_path()) {
+        let registry = match Nix::parse_registry(Nix::system_registry_path()) {
             Ok(registry) => registry,
             Err(error) => {
                 tracing::warn!("{error:?}");
@@ -488,9 +1665,169 @@ impl App {
 
     #[instrument(level = "debug", skip(self))]
     pub fn switch(&self) -> miette::Result<()> {
+        if let Some(bundle) = self.config.bundle()
+            && let Some(manifest) = &self.manifest
+        {
+            self.import_bundle(bundle, manifest)?;
+        }
         self.ensure_packages()?;
         self.ensure_registry()?;
         self.ensure_channels()?;
         Ok(())
     }
+
+    /// Compare the evaluated configuration to the current profile, registry, and channels.
+    #[instrument(level = "debug", skip(self))]
+    pub fn status(&self) -> miette::Result<()> {
+        let mut in_sync = true;
+
+        for profile in self.profiles()? {
+            in_sync &= self.profile_status(&profile)?;
+        }
+
+        let pins = self.pins()?;
+
+        if self.config.registry_pin_root() {
+            let path = self.config.root_registry_path()?;
+            in_sync &= self.registry_status("`root`", &path, &pins)?;
+        } else {
+            tracing::debug!("Skipping `root` registry status, registry pinning is disabled");
+        }
+
+        // Entries in the user registry take precedence over the `root` registry, so stale
+        // entries there shadow our pins. `npingler switch` doesn't manage the user registry, so
+        // this doesn't count as being out of sync.
+        if let Some(path) = self.config.project_paths().user_registry_path()? {
+            self.user_registry_status(&path, &pins)?;
+        }
+
+        if self.config.channels_pin_root() {
+            let profile = self.config.channels_root_profile()?;
+            let channels: Utf8PathBuf = self.eval_npingler_attr("pins.channels", None)?;
+            let current_channels = fs_err::symlink_metadata(&profile)
+                .ok()
+                .and_then(|_| resolve_symlink_utf8(profile.clone()).ok());
+            if current_channels.as_deref() == Some(channels.as_path()) {
+                tracing::info!("Channels are in sync: {channels}");
+            } else {
+                in_sync = false;
+                let current_channels = current_channels
+                    .map(|path| path.to_string())
+                    .unwrap_or_default();
+                tracing::warn!("Channels are out of sync:\n- {current_channels}\n+ {channels}");
+            }
+        } else {
+            tracing::debug!("Skipping channels status, channel pinning is disabled");
+        }
+
+        if in_sync {
+            Ok(())
+        } else {
+            Err(miette!(
+                "Out of sync with configuration; run `npingler switch` to update"
+            ))
+        }
+    }
+
+    /// Check if a profile link points to the evaluated profile.
+    fn profile_status(&self, profile: &ManagedProfile) -> miette::Result<bool> {
+        let description = profile.description();
+        let old_profile = resolve_symlink_utf8(profile.link.clone())
+            .inspect_err(|err| tracing::debug!("Failed to resolve {:?}: {err}", profile.link))
+            .ok();
+        let new_profile: Utf8PathBuf = self.eval_npingler_attr(&profile.attr, None)?;
+        match old_profile {
+            Some(old_profile) if old_profile == new_profile => {
+                tracing::info!("{} is in sync: {new_profile}", description);
+                Ok(true)
+            }
+            Some(old_profile) => {
+                tracing::warn!(
+                    "{} is out of sync:\n{}\n{}",
+                    description,
+                    format!("- {old_profile}").red(),
+                    format!("+ {new_profile}").green()
+                );
+                Ok(false)
+            }
+            None => {
+                tracing::warn!(
+                    "{} {} does not exist:\n{}",
+                    description,
+                    profile.link,
+                    format!("+ {new_profile}").green()
+                );
+                Ok(false)
+            }
+        }
+    }
+
+    /// Warn about entries in the user's Flake registry which shadow `pins`.
+    fn user_registry_status(&self, path: &Utf8Path, pins: &NixPins) -> miette::Result<()> {
+        let Some(registry) = Nix::parse_registry(path)? else {
+            return Ok(());
+        };
+
+        for (name, pin) in &pins.entries {
+            match registry.id_to_path(name) {
+                Some(current) if current == pin => {
+                    tracing::debug!("User registry entry {name} matches the pin: {pin}");
+                }
+                Some(current) => {
+                    tracing::warn!(
+                        "User registry entry {name} in {path} shadows the pinned entry:\n- {current}\n+ {pin}\nRun `nix registry remove {name}` to use the pinned entry instead"
+                    );
+                }
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check if the entries in a Flake registry match `pins`.
+    fn registry_status(
+        &self,
+        description: &str,
+        path: &Utf8Path,
+        pins: &NixPins,
+    ) -> miette::Result<bool> {
+        let registry = Nix::parse_registry(path)?;
+        let mut in_sync = true;
+
+        for (name, pin) in &pins.entries {
+            match registry
+                .as_ref()
+                .and_then(|registry| registry.id_to_path(name))
+            {
+                Some(current) if current == pin => {
+                    tracing::info!("{description} registry entry {name} is in sync: {pin}");
+                }
+                Some(current) => {
+                    in_sync = false;
+                    tracing::warn!(
+                        "{description} registry entry {name} is out of sync:\n- {current}\n+ {pin}"
+                    );
+                }
+                None => {
+                    in_sync = false;
+                    tracing::warn!("{description} registry entry {name} is missing:\n+ {pin}");
+                }
+            }
+        }
+
+        Ok(in_sync)
+    }
+}
+
+/// The package in a profile which provides a command.
+struct CommandProvider {
+    /// The resolved path of the command.
+    target: Utf8PathBuf,
+    /// The store path of the package containing `target`.
+    package: Utf8PathBuf,
+    /// The derivation which built `package`.
+    deriver: Option<Utf8PathBuf>,
+    /// Other packages in the profile which have the command, but aren't linked into it.
+    shadowed: Vec<Utf8PathBuf>,
 }
@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use itertools::Itertools;
+use miette::IntoDiagnostic;
+use rustc_hash::FxHashMap;
+
+use crate::directories::ProjectPaths;
+use crate::nix::BuildError;
+use crate::nix::Derivations;
+use crate::nix::FailedBuild;
+use crate::nix::Nix;
+use crate::nix::drv_name;
+
+/// How many lines of a failed build's log to show.
+const SHOWN_LOG_LINES: usize = 20;
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error(
+    "{} {} failed to build",
+    failed.len(),
+    if failed.len() == 1 { "derivation" } else { "derivations" }
+)]
+pub struct BuildFailure {
+    #[related]
+    failed: Vec<FailedDerivation>,
+}
+
+impl BuildFailure {
+    /// Explain a failed build, saving the failed derivations' logs.
+    ///
+    /// If `root` is given, each failed derivation is shown with the chain of dependencies from
+    /// `root` to it.
+    pub fn new(
+        nix: &Nix,
+        project_paths: &ProjectPaths,
+        error: BuildError,
+        root: Option<&Utf8Path>,
+    ) -> Self {
+        let closure = root.and_then(|root| {
+            nix.derivation_closure(root)
+                .inspect_err(|err| tracing::debug!("Failed to get closure of {root}:\n{err}"))
+                .ok()
+        });
+        let log_dir = project_paths
+            .log_dir()
+            .inspect_err(|err| tracing::warn!("{err}"))
+            .ok();
+
+        Self {
+            failed: error
+                .failed
+                .into_iter()
+                .map(|failed| {
+                    FailedDerivation::new(
+                        nix,
+                        log_dir.as_deref(),
+                        failed,
+                        root.zip(closure.as_ref()),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("Failed to build `{name}`")]
+#[diagnostic(help("{help}"))]
+pub struct FailedDerivation {
+    name: String,
+    help: String,
+}
+
+impl FailedDerivation {
+    fn new(
+        nix: &Nix,
+        log_dir: Option<&Utf8Path>,
+        failed: FailedBuild,
+        closure: Option<(&Utf8Path, &Derivations)>,
+    ) -> Self {
+        let name = drv_name(failed.drv.as_str());
+        let mut help = format!("Derivation: {}", failed.drv);
+
+        if let Some((root, closure)) = closure
+            && let Some(chain) = dependency_chain(closure, root, &failed.drv)
+            && chain.len() > 1
+        {
+            help.push_str(&format!(
+                "\nRequired by: {}",
+                chain.iter().map(|drv| drv_name(drv.as_str())).join(" → ")
+            ));
+        }
+
+        // `nix log` has the full log, but if Nix didn't store it, fall back to the lines we saw
+        // while building.
+        let log = nix
+            .log(&failed.drv)
+            .inspect_err(|err| tracing::debug!("Failed to get log for {}:\n{err}", failed.drv))
+            .ok()
+            .filter(|log| !log.trim().is_empty())
+            .unwrap_or_else(|| failed.log_tail.join("\n"));
+
+        if let Some(log_dir) = log_dir {
+            match save_log(log_dir, &name, &log) {
+                Ok(path) => help.push_str(&format!("\nFull log: {path}")),
+                Err(err) => tracing::warn!("Failed to save build log for {name}: {err}"),
+            }
+        }
+
+        let lines = log.lines().collect::<Vec<_>>();
+        if !lines.is_empty() {
+            help.push_str(&format!(
+                "\nLast {} lines of the build log:\n{}",
+                lines.len().min(SHOWN_LOG_LINES),
+                lines[lines.len().saturating_sub(SHOWN_LOG_LINES)..].join("\n")
+            ));
+        }
+
+        Self { name, help }
+    }
+}
+
+fn save_log(log_dir: &Utf8Path, name: &str, log: &str) -> miette::Result<Utf8PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .into_diagnostic()?
+        .as_secs();
+    let path = log_dir.join(format!("{name}-{timestamp}.log"));
+    fs_err::write(&path, log).into_diagnostic()?;
+    Ok(path)
+}
+
+/// Find the shortest chain of derivations from `root` to `target` through their inputs.
+fn dependency_chain(
+    closure: &Derivations,
+    root: &Utf8Path,
+    target: &Utf8Path,
+) -> Option<Vec<Utf8PathBuf>> {
+    let mut parents: FxHashMap<&Utf8Path, &Utf8Path> = FxHashMap::default();
+    let mut queue = VecDeque::from([root]);
+
+    while let Some(drv) = queue.pop_front() {
+        if drv == target {
+            let mut chain = vec![drv.to_owned()];
+            let mut current = drv;
+            while let Some(parent) = parents.get(current) {
+                chain.push(parent.to_path_buf());
+                current = parent;
+            }
+            chain.reverse();
+            return Some(chain);
+        }
+
+        if let Some(derivation) = closure.0.get(drv) {
+            for input in derivation.input_drvs.keys() {
+                let input = input.as_path();
+                if input != root && !parents.contains_key(input) {
+                    parents.insert(input, drv);
+                    queue.push_back(input);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nix::derivations;
+
+    #[test]
+    fn test_message() {
+        let failure = |count| BuildFailure {
+            failed: (0..count)
+                .map(|_| FailedDerivation {
+                    name: "hello-2.12".to_owned(),
+                    help: String::new(),
+                })
+                .collect(),
+        };
+        assert_eq!(failure(1).to_string(), "1 derivation failed to build");
+        assert_eq!(failure(2).to_string(), "2 derivations failed to build");
+    }
+
+    #[test]
+    fn test_dependency_chain() {
+        let closure = derivations(&[
+            (
+                "/nix/store/aaa-profile.drv",
+                &[("out", "/nix/store/aaa-profile", None)],
+                &["/nix/store/bbb-hello-2.12.drv", "/nix/store/ccc-gcc.drv"],
+                &[],
+            ),
+            (
+                "/nix/store/bbb-hello-2.12.drv",
+                &[("out", "/nix/store/bbb-hello-2.12", None)],
+                &["/nix/store/ccc-gcc.drv"],
+                &[],
+            ),
+            (
+                "/nix/store/ccc-gcc.drv",
+                &[("out", "/nix/store/ccc-gcc", None)],
+                &["/nix/store/ddd-glibc.drv"],
+                &[],
+            ),
+            (
+                "/nix/store/ddd-glibc.drv",
+                &[("out", "/nix/store/ddd-glibc", None)],
+                &[],
+                &[],
+            ),
+            (
+                "/nix/store/eee-unrelated.drv",
+                &[("out", "/nix/store/eee-unrelated", None)],
+                &[],
+                &[],
+            ),
+        ]);
+        let root = Utf8Path::new("/nix/store/aaa-profile.drv");
+
+        // The shortest chain is found.
+        assert_eq!(
+            dependency_chain(&closure, root, Utf8Path::new("/nix/store/ddd-glibc.drv")).unwrap(),
+            [
+                "/nix/store/aaa-profile.drv",
+                "/nix/store/ccc-gcc.drv",
+                "/nix/store/ddd-glibc.drv",
+            ]
+        );
+        assert_eq!(dependency_chain(&closure, root, root).unwrap(), [root]);
+        assert_eq!(
+            dependency_chain(
+                &closure,
+                root,
+                Utf8Path::new("/nix/store/eee-unrelated.drv")
+            ),
+            None
+        );
+    }
+}
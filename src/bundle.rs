@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::miette;
+
+use crate::pins::NixPins;
+
+/// The name of the manifest file in a bundle directory.
+const MANIFEST_FILE: &str = "npingler-bundle.json";
+
+/// The current manifest format version.
+const MANIFEST_VERSION: u32 = 1;
+
+/// Store paths to switch to instead of evaluating `default.nix`, recorded when a bundle is
+/// created or given with `switch --store-path`.
+///
+/// A bundle is a directory containing a `file://` binary cache with the closures of these paths
+/// and a manifest listing them, so that a machine without network access can switch to them
+/// without evaluating `default.nix`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    /// The `npingler.<host>` attribute the bundle was built from.
+    pub host: String,
+    /// Profile names to built profile store paths.
+    pub profiles: BTreeMap<String, Utf8PathBuf>,
+    /// The pinned sources, for the Nix Flake registry.
+    #[serde(default)]
+    pub pins: Option<NixPins>,
+    /// The `channels` derivation.
+    #[serde(default)]
+    pub channels: Option<Utf8PathBuf>,
+}
+
+impl Manifest {
+    pub fn new(host: &str) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            host: host.to_owned(),
+            profiles: BTreeMap::new(),
+            pins: None,
+            channels: None,
+        }
+    }
+
+    /// A manifest for a single prebuilt profile, given with `switch --store-path`.
+    pub fn from_store_path(
+        host: &str,
+        profile_name: &str,
+        store_path: &Utf8Path,
+        pins_file: Option<&Utf8Path>,
+    ) -> miette::Result<Self> {
+        let store_path = crate::profiles::check_profile_store_path(store_path)?;
+
+        let mut manifest = Self::new(host);
+        manifest
+            .profiles
+            .insert(profile_name.to_owned(), store_path);
+        if let Some(path) = pins_file {
+            let contents = fs_err::read_to_string(path).into_diagnostic()?;
+            let pins: NixPins = serde_json::from_str(&contents)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to parse pins from {path}"))?;
+            manifest.pins = Some(pins);
+        }
+        Ok(manifest)
+    }
+
+    /// Read the manifest from a bundle directory.
+    pub fn read(bundle: &Utf8Path) -> miette::Result<Self> {
+        let path = bundle.join(MANIFEST_FILE);
+        let contents = fs_err::read_to_string(&path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("{bundle} is not an `npingler` bundle"))?;
+        let manifest: Self = serde_json::from_str(&contents)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to parse bundle manifest {path}"))?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(miette!(
+                "Bundle manifest {path} has version {}, but this version of `npingler` only supports version {MANIFEST_VERSION}",
+                manifest.version
+            ));
+        }
+        Ok(manifest)
+    }
+
+    /// Write the manifest into a bundle directory.
+    pub fn write(&self, bundle: &Utf8Path) -> miette::Result<()> {
+        let path = bundle.join(MANIFEST_FILE);
+        let contents = serde_json::to_string_pretty(self).into_diagnostic()?;
+        fs_err::write(&path, contents)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to write bundle manifest {path}"))
+    }
+
+    /// All the store paths recorded in the manifest.
+    pub fn store_paths(&self) -> Vec<&Utf8Path> {
+        let mut paths = self
+            .profiles
+            .values()
+            .map(|path| path.as_path())
+            .collect::<Vec<_>>();
+        if let Some(pins) = &self.pins {
+            paths.extend(pins.entries.values().map(|path| path.as_path()));
+        }
+        if let Some(channels) = &self.channels {
+            paths.push(channels);
+        }
+        paths
+    }
+}
+
+/// Get the `file://` store URL for a bundle directory.
+pub fn store_url(bundle: &Utf8Path) -> miette::Result<String> {
+    let bundle = camino::absolute_utf8(bundle)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to make bundle path absolute: {bundle}"))?;
+    Ok(format!("file://{bundle}"))
+}
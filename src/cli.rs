@@ -1,7 +1,14 @@
+use std::collections::BTreeSet;
+
 use camino::Utf8PathBuf;
+use clap::ArgMatches;
+use clap::CommandFactory;
+use clap::FromArgMatches;
+use clap::parser::ValueSource;
 
 use crate::clap::ShellWords;
 use crate::directories::ProjectPaths;
+use crate::privilege::PrivilegeCommand;
 
 /// A friendly Nix profile manager.
 #[derive(Debug, Clone, clap::Parser)]
@@ -29,6 +36,10 @@ pub struct Args {
 
     #[command(subcommand)]
     pub command: Command,
+
+    /// The IDs of arguments which were set from environment variables rather than flags.
+    #[arg(skip)]
+    pub env_args: BTreeSet<String>,
 }
 
 #[derive(Debug, Clone, clap::Subcommand)]
@@ -39,6 +50,11 @@ pub enum Command {
         #[arg(long)]
         no_switch: bool,
 
+        /// Update the pins in a temporary copy of the configuration and show how the pins and
+        /// packages would change, without changing `npins/sources.json` or any profiles.
+        #[arg(long, conflicts_with = "no_switch")]
+        preview: bool,
+
         #[command(flatten)]
         switch_args: SwitchArgs,
     },
@@ -46,6 +62,27 @@ pub enum Command {
     /// Build the current profile and switch to it (alias: install).
     #[command(alias = "install")]
     Switch {
+        /// Switch to the store paths recorded in a bundle created with `npingler bundle`,
+        /// without evaluating `default.nix`.
+        ///
+        /// The bundle's store paths are imported into the Nix store first. This requires being
+        /// a trusted user, because the paths are not signed.
+        #[arg(long, value_name = "BUNDLE")]
+        from_bundle: Option<Utf8PathBuf>,
+
+        /// Switch to a profile that's already built, like one built in CI, without evaluating
+        /// `default.nix`.
+        ///
+        /// The path is switched to the base profile, or the profile given with
+        /// `--profile-name`.
+        #[arg(long, value_name = "PATH", conflicts_with = "from_bundle")]
+        store_path: Option<Utf8PathBuf>,
+
+        /// With `--store-path`, a JSON file mapping Nix Flake registry names to pinned source
+        /// paths, like the output of `nix eval --json --file default.nix npingler.<host>.pins.pins`.
+        #[arg(long, value_name = "FILE", requires = "store_path")]
+        pins: Option<Utf8PathBuf>,
+
         #[command(flatten)]
         switch_args: SwitchArgs,
     },
@@ -56,9 +93,165 @@ pub enum Command {
         switch_args: SwitchArgs,
     },
 
+    /// Build the profiles and export their closures, the channels, and the pinned sources to a
+    /// bundle directory.
+    ///
+    /// The bundle can be copied to a machine without network access and applied with
+    /// `npingler switch --from-bundle`.
+    Bundle {
+        /// The directory to write the bundle to.
+        #[arg(short, long)]
+        output: Utf8PathBuf,
+
+        #[command(flatten)]
+        switch_args: SwitchArgs,
+    },
+
+    /// Add packages to `packages.toml`, next to `default.nix`.
+    ///
+    /// Each package is checked to exist in the pinned `nixpkgs` first. `default.nix` must pass
+    /// `packagesFile = ./packages.toml;` (and `host`) to `makeProfile` to use the list.
+    Add {
+        /// `nixpkgs` attribute paths of the packages to add, like `ripgrep` or
+        /// `python3Packages.black`.
+        #[arg(required = true)]
+        packages: Vec<String>,
+
+        /// Add the packages for every host, rather than only the current host.
+        #[arg(long)]
+        shared: bool,
+
+        /// Switch to the updated profile.
+        #[arg(long)]
+        switch: bool,
+
+        #[command(flatten)]
+        switch_args: SwitchArgs,
+    },
+
+    /// Remove packages from `packages.toml`, next to `default.nix`.
+    Remove {
+        /// `nixpkgs` attribute paths of the packages to remove.
+        #[arg(required = true)]
+        packages: Vec<String>,
+
+        /// Remove the packages from the list for every host, rather than the current host's
+        /// list.
+        #[arg(long)]
+        shared: bool,
+
+        /// Switch to the updated profile.
+        #[arg(long)]
+        switch: bool,
+
+        #[command(flatten)]
+        switch_args: SwitchArgs,
+    },
+
+    /// Search for packages in the pinned `nixpkgs`, the same version your profile uses.
+    ///
+    /// Packages already installed in a profile are marked.
+    Search {
+        /// Regular expressions to search package names and descriptions for. Packages must
+        /// match every term.
+        #[arg(required = true)]
+        terms: Vec<String>,
+
+        /// The pin to search.
+        #[arg(long, default_value = "nixpkgs")]
+        pin: String,
+
+        #[command(flatten)]
+        switch_args: SwitchArgs,
+    },
+
+    /// List the packages installed in your profiles.
+    ///
+    /// Descriptions, licenses, and homepages are evaluated from the configuration, unless
+    /// `--no-eval` is given.
+    List {
+        /// Only list packages whose name or description matches this regular expression
+        /// (case-insensitive).
+        filter: Option<String>,
+
+        /// Print the packages as JSON.
+        #[arg(long)]
+        json: bool,
+
+        /// Don't evaluate the configuration for package metadata.
+        #[arg(long)]
+        no_eval: bool,
+
+        #[command(flatten)]
+        switch_args: SwitchArgs,
+    },
+
+    /// Show which package in your profiles provides a command, and any other packages in the
+    /// same profile which also provide it but lost the collision.
+    Which {
+        /// The name of the command, like `rg`.
+        command: String,
+
+        #[command(flatten)]
+        switch_args: SwitchArgs,
+    },
+
+    /// List the hosts defined in the `npingler` attribute set.
+    Hosts {
+        #[command(flatten)]
+        switch_args: SwitchArgs,
+    },
+
+    /// Commands to inspect the profiles defined for the current host.
+    #[command(subcommand)]
+    Profiles(ProfilesCommand),
+
+    /// Check if the current profile, registry, and channels match the configuration, without
+    /// building anything.
+    ///
+    /// Exits with a non-zero status if anything is out of sync.
+    Status {
+        #[command(flatten)]
+        switch_args: SwitchArgs,
+    },
+
+    /// Check the environment for problems and suggest fixes.
+    Doctor {
+        #[command(flatten)]
+        switch_args: SwitchArgs,
+    },
+
+    /// Create a new configuration: pin `nixpkgs` and `npingler` with `npins`, write a
+    /// `default.nix` for the current host, and build it.
+    ///
+    /// Use `config init` to only write a `config.toml`.
+    Init {
+        /// The directory to create the configuration in. Defaults to `~/.config/npingler`.
+        directory: Option<Utf8PathBuf>,
+
+        /// Add the packages installed in your current Nix profile to the new configuration.
+        #[arg(long)]
+        import_profile: bool,
+
+        /// Don't build the new configuration.
+        #[arg(long)]
+        no_build: bool,
+
+        /// The `nixpkgs` channel to pin.
+        #[arg(long, default_value = "nixpkgs-unstable")]
+        nixpkgs_channel: String,
+
+        /// The GitHub repository to pin `npingler` from, as `OWNER/REPO`.
+        #[arg(long, default_value = "9999years/npingler")]
+        npingler_repo: String,
+
+        #[command(flatten)]
+        switch_args: SwitchArgs,
+    },
+
     // TODO: `pin-channels` and `pin-registry` commands would be nice, but the defaults (not
     // pinning channels or the registry) make the behavior very unintuitive.
-    /// Commands to initialize the `npingler` configuration file.
+    /// Commands to initialize and inspect the `npingler` configuration.
     #[command(subcommand)]
     Config(ConfigCommand),
 
@@ -68,6 +261,7 @@ pub enum Command {
 }
 
 #[derive(Debug, Clone, clap::Subcommand)]
+#[expect(clippy::large_enum_variant)]
 pub enum ConfigCommand {
     /// Generate a default `config.toml` file.
     Init {
@@ -75,6 +269,37 @@ pub enum ConfigCommand {
         /// `~/.config/npingler/config.toml`.
         output: Option<String>,
     },
+
+    /// Show the effective configuration and where each setting came from.
+    ///
+    /// Configuration files are loaded from the system configuration directories (e.g.
+    /// `/etc/xdg/npingler/config.toml`) first and the user configuration directory last, so that
+    /// user settings override system settings. Command-line arguments override both.
+    Show {
+        #[command(flatten)]
+        switch_args: SwitchArgs,
+    },
+
+    /// Rewrite deprecated settings in the configuration file, preserving comments and
+    /// formatting.
+    ///
+    /// Only the user configuration file (or the `--config` file) is rewritten; deprecated
+    /// settings in system configuration files are reported instead.
+    ///
+    /// Use `--dry` to print the changes without writing them.
+    Migrate,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+pub enum ProfilesCommand {
+    /// List the profiles defined for the current host and the Nix profiles they're switched to.
+    ///
+    /// The `base` profile is `npingler.<host>.packages`; other profiles come from
+    /// `npingler.<host>.profiles.<name>`.
+    List {
+        #[command(flatten)]
+        switch_args: SwitchArgs,
+    },
 }
 
 #[derive(Debug, Clone, clap::Subcommand)]
@@ -116,6 +341,9 @@ pub struct SwitchArgs {
 
     #[command(flatten)]
     pub nix: NixCommandArgs,
+
+    #[command(flatten)]
+    pub privilege: PrivilegeArgs,
 }
 
 #[derive(Debug, Default, Clone, clap::Args)]
@@ -149,6 +377,28 @@ pub struct ProfileArgs {
     #[arg(long, env = "NIX_PROFILE")]
     pub profile: Option<Utf8PathBuf>,
 
+    /// Only build or switch the named profile. May be given multiple times.
+    ///
+    /// `base` refers to `npingler.<host>.packages`; other names refer to
+    /// `npingler.<host>.profiles.<name>`. Defaults to all profiles.
+    #[arg(long = "profile-name", value_name = "NAME")]
+    pub profile_names: Vec<String>,
+
+    /// Manage the default system-wide profile, `/nix/var/nix/profiles/default`, which is on
+    /// every user's `$PATH` on multi-user Nix installs.
+    ///
+    /// Switching the profile requires `root` privileges. Not supported on NixOS, where system
+    /// packages are managed by the NixOS configuration.
+    #[arg(long)]
+    pub system: bool,
+
+    /// Fail if packages in a profile provide the same file with different contents, even when
+    /// `buildEnv` would pick one of them by priority.
+    ///
+    /// Collisions between packages with the same priority always fail the build.
+    #[arg(long)]
+    pub strict_collisions: bool,
+
     /// Shell-quoted extra arguments to pass to `nix-env --set ...` when switching to the new
     /// profile.
     #[arg(long, hide = true)]
@@ -162,6 +412,18 @@ pub struct ProfileArgs {
     pub diff_derivations: Option<String>,
 }
 
+#[derive(Debug, Default, Clone, clap::Args)]
+#[clap(next_help_heading = "Privilege escalation options")]
+pub struct PrivilegeArgs {
+    /// The command used to run commands as `root` when pinning the `root` registry or channels,
+    /// or when switching the system-wide profile with `--system`.
+    ///
+    /// One of `sudo`, `doas`, `run0`, `pkexec`, or `none`, or a shell-quoted custom command.
+    /// Ignored if `npingler` is already running as `root`.
+    #[arg(long)]
+    pub privilege_command: Option<PrivilegeCommand>,
+}
+
 #[derive(Debug, Clone, clap::Args)]
 #[clap(next_help_heading = "Logging options")]
 pub struct LogArgs {
@@ -202,9 +464,31 @@ pub struct NixCommandArgs {
     /// Extra args for `nix-env --set` invocations.
     #[arg(long)]
     pub extra_nix_env_set_args: Option<ShellWords>,
+
+    /// Don't use the network: pass `--offline` and disable substituters for every Nix command.
+    ///
+    /// Before building, paths that would need to be downloaded are listed, so that builds fail
+    /// early instead of partway through.
+    #[arg(long)]
+    pub offline: bool,
 }
 
 impl Args {
+    /// Parse the command-line arguments, recording which were set from environment variables.
+    pub fn parse_with_sources() -> Self {
+        let matches = Self::command().get_matches();
+        let mut args = Self::from_arg_matches(&matches)
+            .map_err(|err| err.format(&mut Self::command()))
+            .unwrap_or_else(|err| err.exit());
+        args.env_args = env_args(&matches);
+        args
+    }
+
+    /// Was an argument (like `hostname`) set from its environment variable rather than a flag?
+    pub fn set_from_env(&self, id: &str) -> bool {
+        self.env_args.contains(id)
+    }
+
     pub fn config_paths(&self, project_paths: &ProjectPaths) -> miette::Result<Vec<Utf8PathBuf>> {
         if let Some(path) = &self.config {
             return Ok(vec![path.clone()]);
@@ -229,3 +513,16 @@ impl Args {
         if ret.is_empty() { None } else { Some(ret) }
     }
 }
+
+/// Get the IDs of arguments set from environment variables, including in subcommands.
+fn env_args(matches: &ArgMatches) -> BTreeSet<String> {
+    let mut ret: BTreeSet<String> = matches
+        .ids()
+        .filter(|id| matches.value_source(id.as_str()) == Some(ValueSource::EnvVariable))
+        .map(|id| id.as_str().to_owned())
+        .collect();
+    if let Some((_, subcommand)) = matches.subcommand() {
+        ret.extend(env_args(subcommand));
+    }
+    ret
+}
@@ -0,0 +1,613 @@
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use itertools::Itertools;
+use miette::IntoDiagnostic;
+use serde::Deserialize;
+
+use crate::nix::Derivation;
+use crate::nix::Derivations;
+use crate::nix::drv_name;
+use crate::nix::parse_name;
+
+/// How many colliding files to list for each group of packages.
+const SHOWN_FILES: usize = 5;
+
+/// A package linked into a `buildEnv`, from its `pkgs` attribute.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BuildEnvPackage {
+    /// The outputs to link.
+    paths: Vec<Utf8PathBuf>,
+    /// Lower numbers win collisions.
+    priority: i64,
+}
+
+impl BuildEnvPackage {
+    fn name(&self) -> String {
+        self.paths
+            .first()
+            .map(|path| drv_name(path.as_str()))
+            .unwrap_or_default()
+    }
+}
+
+/// The packages a `buildEnv` derivation links together, and whether it ignores collisions.
+#[derive(Debug, Clone)]
+pub struct BuildEnv {
+    packages: Vec<BuildEnvPackage>,
+    /// The paths within each package to link, like `/bin`. `/` links everything.
+    paths_to_link: Vec<String>,
+    ignore_collisions: bool,
+    check_collision_contents: bool,
+}
+
+impl BuildEnv {
+    /// Read the `buildEnv` arguments from a derivation's environment, or `None` if it isn't a
+    /// `buildEnv`.
+    pub fn from_derivation(drv: &Derivation) -> Option<Self> {
+        let packages = drv.env.get("pkgs")?;
+        let packages = serde_json::from_str(packages)
+            .inspect_err(|err| tracing::debug!("Failed to parse `buildEnv` packages: {err}"))
+            .ok()?;
+        let flag = |name: &str| drv.env.get(name).is_some_and(|value| value == "1");
+        let paths_to_link = drv
+            .env
+            .get("pathsToLink")
+            .map(|paths| paths.split_whitespace().map(str::to_owned).collect())
+            .unwrap_or_else(|| vec!["/".to_owned()]);
+        Some(Self {
+            packages,
+            paths_to_link,
+            ignore_collisions: flag("ignoreCollisions"),
+            check_collision_contents: flag("checkCollisionContents"),
+        })
+    }
+
+    /// Get the installables (like `/nix/store/...-hello.drv^out,man`) which build the linked
+    /// packages, from the `buildEnv` derivation's inputs.
+    ///
+    /// Other inputs, like the builder script's dependencies, aren't included.
+    pub fn installables(&self, inputs: &Derivations) -> Vec<String> {
+        let paths = self
+            .packages
+            .iter()
+            .flat_map(|package| &package.paths)
+            .collect::<BTreeSet<_>>();
+        inputs
+            .0
+            .iter()
+            .filter_map(|input| {
+                let outputs = input
+                    .outputs
+                    .iter()
+                    .filter(|(_, output)| paths.contains(&output.path))
+                    .map(|(name, _)| name.as_str())
+                    .sorted()
+                    .join(",");
+                (!outputs.is_empty()).then(|| format!("{}^{outputs}", input.path))
+            })
+            .sorted()
+            .collect()
+    }
+
+    /// Are all of the packages in the Nix store already?
+    pub fn is_built(&self) -> bool {
+        self.packages
+            .iter()
+            .flat_map(|package| &package.paths)
+            .all(|path| path.exists())
+    }
+
+    /// Find files provided by more than one package with different contents.
+    ///
+    /// The packages must already be in the Nix store.
+    pub fn collisions(&self) -> miette::Result<Vec<Collision>> {
+        // Relative paths to the packages which provide them.
+        let mut files: BTreeMap<Utf8PathBuf, Vec<(usize, Utf8PathBuf)>> = BTreeMap::new();
+        for (index, package) in self.packages.iter().enumerate() {
+            for root in &package.paths {
+                // Single-file outputs aren't linked.
+                if root.is_dir() {
+                    self.find_files(index, root, Utf8Path::new(""), &mut files)?;
+                }
+            }
+        }
+
+        // Group colliding files by the packages involved.
+        let mut groups: BTreeMap<(Vec<usize>, Option<usize>), Vec<Utf8PathBuf>> = BTreeMap::new();
+        for (relative, providers) in files {
+            if providers.len() < 2 {
+                continue;
+            }
+            let (first, first_path) = &providers[0];
+            let mut differing = vec![*first];
+            for (index, path) in &providers[1..] {
+                if !self.same_contents(first_path, path)? {
+                    differing.push(*index);
+                }
+            }
+            if differing.len() < 2 {
+                continue;
+            }
+
+            let min_priority = differing
+                .iter()
+                .map(|index| self.packages[*index].priority)
+                .min()
+                .expect("At least two packages collide");
+            let winners = differing
+                .iter()
+                .filter(|index| self.packages[**index].priority == min_priority)
+                .collect::<Vec<_>>();
+            // With equal priorities, `buildEnv` fails unless it ignores collisions, in which case
+            // the first package wins.
+            let winner = match winners.as_slice() {
+                [winner] => Some(**winner),
+                _ if self.ignore_collisions => Some(*winners[0]),
+                _ => None,
+            };
+            differing.sort();
+            groups
+                .entry((differing, winner))
+                .or_default()
+                .push(relative);
+        }
+
+        Ok(groups
+            .into_iter()
+            .map(|((packages, winner), files)| {
+                Collision::new(
+                    packages
+                        .iter()
+                        .map(|index| &self.packages[*index])
+                        .collect(),
+                    winner.map(|index| &self.packages[index]),
+                    files,
+                )
+            })
+            .collect())
+    }
+
+    /// Record the files `buildEnv` would link from the package at `root`, like its `builder.pl`.
+    ///
+    /// Symlinks to directories are followed, and other symlinks (including dangling ones) are
+    /// linked like files.
+    fn find_files(
+        &self,
+        index: usize,
+        root: &Utf8Path,
+        relative: &Utf8Path,
+        files: &mut BTreeMap<Utf8PathBuf, Vec<(usize, Utf8PathBuf)>>,
+    ) -> miette::Result<()> {
+        let directory = root.join(relative);
+        for entry in fs_err::read_dir(&directory).into_diagnostic()? {
+            let entry = entry.into_diagnostic()?;
+            let Ok(name) = entry.file_name().into_string() else {
+                tracing::debug!("Skipping non-UTF-8 path in {directory}: {:?}", entry.path());
+                continue;
+            };
+            let relative = relative.join(&name);
+            if is_ignored(&relative, &name) || !self.is_linked(&relative) {
+                continue;
+            }
+
+            let path = directory.join(&name);
+            // This follows symlinks, so dangling symlinks aren't directories.
+            if path.is_dir() {
+                self.find_files(index, root, &relative, files)?;
+            } else {
+                let providers = files.entry(relative).or_default();
+                if !providers.iter().any(|(other, _)| *other == index) {
+                    providers.push((index, path));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Is `relative` in (or a parent of) one of the `pathsToLink`?
+    fn is_linked(&self, relative: &Utf8Path) -> bool {
+        let relative = format!("/{relative}");
+        self.paths_to_link.iter().any(|path| {
+            let path = path.trim_end_matches('/');
+            path.is_empty()
+                || relative == path
+                || relative.starts_with(&format!("{path}/"))
+                || path.starts_with(&format!("{relative}/"))
+        })
+    }
+
+    /// Would `buildEnv` consider these files the same?
+    fn same_contents(&self, a: &Utf8Path, b: &Utf8Path) -> miette::Result<bool> {
+        // Dangling symlinks always collide.
+        let (Ok(a), Ok(b)) = (fs_err::canonicalize(a), fs_err::canonicalize(b)) else {
+            return Ok(false);
+        };
+        if a == b {
+            return Ok(true);
+        }
+        if !self.check_collision_contents {
+            return Ok(false);
+        }
+        let a_metadata = fs_err::metadata(&a).into_diagnostic()?;
+        let b_metadata = fs_err::metadata(&b).into_diagnostic()?;
+        if a_metadata.permissions().mode() != b_metadata.permissions().mode()
+            || a_metadata.len() != b_metadata.len()
+        {
+            return Ok(false);
+        }
+        Ok(hash_file(&a)? == hash_file(&b)?)
+    }
+}
+
+/// Paths `buildEnv` never links.
+fn is_ignored(relative: &Utf8Path, name: &str) -> bool {
+    let relative = relative.as_str();
+    relative == "nix-support"
+        || relative == "propagated-build-inputs"
+        || relative.ends_with("info/dir")
+        || (relative.starts_with("share/mime/") && !relative.starts_with("share/mime/packages"))
+        || name == "perllocal.pod"
+        || name == "log"
+}
+
+fn hash_file(path: &std::path::Path) -> miette::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    let mut file = fs_err::File::open(path).into_diagnostic()?;
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).into_diagnostic()?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Packages in a profile which provide the same files with different contents.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{message}")]
+#[diagnostic(help("{help}"))]
+pub struct Collision {
+    message: String,
+    help: String,
+    /// Does `buildEnv` pick one of the packages without failing?
+    pub silent: bool,
+}
+
+impl Collision {
+    fn new(
+        packages: Vec<&BuildEnvPackage>,
+        winner: Option<&BuildEnvPackage>,
+        files: Vec<Utf8PathBuf>,
+    ) -> Self {
+        let names = packages
+            .iter()
+            .map(|package| format!("`{}`", package.name()))
+            .join(" and ");
+        let mut shown = files
+            .iter()
+            .take(SHOWN_FILES)
+            .map(|file| format!("\n• {file}"))
+            .join("");
+        if files.len() > SHOWN_FILES {
+            shown.push_str(&format!("\n• and {} more", files.len() - SHOWN_FILES));
+        }
+
+        match winner {
+            Some(winner) => {
+                let losers = packages
+                    .iter()
+                    .filter(|package| package.name() != winner.name())
+                    .map(|package| format!("`{}` (priority {})", package.name(), package.priority))
+                    .join(", ");
+                Self {
+                    message: format!(
+                        "{names} provide {} of the same files; `{}` (priority {}) wins over {losers}:{shown}",
+                        files.len(),
+                        winner.name(),
+                        winner.priority,
+                    ),
+                    help: format!(
+                        "If that's not what you want, wrap another package in `lib.hiPrio` (like \
+                        `(lib.hiPrio pkgs.{})`) or remove one of the packages",
+                        packages
+                            .iter()
+                            .find(|package| package.name() != winner.name())
+                            .map(|package| attr_guess(package))
+                            .unwrap_or_default(),
+                    ),
+                    silent: true,
+                }
+            }
+            None => {
+                let example = packages
+                    .first()
+                    .map(|package| attr_guess(package))
+                    .unwrap_or_default();
+                Self {
+                    message: format!(
+                        "{names} provide {} of the same files with the same priority:{shown}",
+                        files.len(),
+                    ),
+                    help: format!(
+                        "Choose which package wins by wrapping it in `lib.hiPrio` (like \
+                        `(lib.hiPrio pkgs.{example})`) or the others in `lib.lowPrio`, or \
+                        remove one of the packages"
+                    ),
+                    silent: false,
+                }
+            }
+        }
+    }
+}
+
+/// Guess a package's `nixpkgs` attribute from its name, for suggestions.
+fn attr_guess(package: &BuildEnvPackage) -> String {
+    parse_name(&package.name()).0.to_owned()
+}
+
+/// File collisions between the packages in a profile.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("Packages in the {description} provide the same files")]
+pub struct Collisions {
+    description: String,
+    #[related]
+    collisions: Vec<Collision>,
+}
+
+impl Collisions {
+    pub fn new(description: String, collisions: Vec<Collision>) -> Self {
+        Self {
+            description,
+            collisions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nix::derivations;
+
+    use std::os::unix::fs::symlink;
+
+    /// A package in `dir` with the given files (and symlinks, for contents starting with `->`).
+    fn package(
+        dir: &Utf8Path,
+        name: &str,
+        priority: i64,
+        files: &[(&str, &str)],
+    ) -> BuildEnvPackage {
+        let root = dir.join(format!("00000000000000000000000000000000-{name}"));
+        for (file, contents) in files {
+            let path = root.join(file);
+            fs_err::create_dir_all(path.parent().unwrap()).unwrap();
+            match contents.strip_prefix("->") {
+                Some(target) => symlink(target, &path).unwrap(),
+                None => fs_err::write(&path, contents).unwrap(),
+            }
+        }
+        BuildEnvPackage {
+            paths: vec![root],
+            priority,
+        }
+    }
+
+    fn build_env(packages: Vec<BuildEnvPackage>) -> BuildEnv {
+        BuildEnv {
+            packages,
+            paths_to_link: vec!["/".to_owned()],
+            ignore_collisions: false,
+            check_collision_contents: false,
+        }
+    }
+
+    fn tempdir() -> (tempfile::TempDir, Utf8PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::try_from(dir.path().to_owned()).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn test_installables() {
+        let env = BuildEnv {
+            packages: vec![
+                BuildEnvPackage {
+                    paths: vec![
+                        "/nix/store/bbb-hello-2.12".into(),
+                        "/nix/store/bbb-hello-2.12-man".into(),
+                    ],
+                    priority: 5,
+                },
+                BuildEnvPackage {
+                    paths: vec!["/nix/store/ccc-ripgrep-14.1.0".into()],
+                    priority: 5,
+                },
+            ],
+            paths_to_link: vec!["/".to_owned()],
+            ignore_collisions: false,
+            check_collision_contents: false,
+        };
+        let inputs = derivations(&[
+            (
+                "/nix/store/bbb-hello-2.12.drv",
+                &[
+                    ("out", "/nix/store/bbb-hello-2.12", None),
+                    ("man", "/nix/store/bbb-hello-2.12-man", None),
+                    ("dev", "/nix/store/bbb-hello-2.12-dev", None),
+                ],
+                &[],
+                &[],
+            ),
+            (
+                "/nix/store/ccc-ripgrep-14.1.0.drv",
+                &[("out", "/nix/store/ccc-ripgrep-14.1.0", None)],
+                &[],
+                &[],
+            ),
+            // Used by the builder, but not linked.
+            (
+                "/nix/store/ddd-perl-5.40.0.drv",
+                &[("out", "/nix/store/ddd-perl-5.40.0", None)],
+                &[],
+                &[],
+            ),
+        ]);
+        assert_eq!(
+            env.installables(&inputs),
+            [
+                "/nix/store/bbb-hello-2.12.drv^man,out",
+                "/nix/store/ccc-ripgrep-14.1.0.drv^out",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collisions_same_priority() {
+        let (_dir, dir) = tempdir();
+        let env = build_env(vec![
+            package(&dir, "a", 5, &[("bin/tool", "a"), ("bin/a", "a")]),
+            package(&dir, "b", 5, &[("bin/tool", "b"), ("bin/b", "b")]),
+        ]);
+        let collisions = env.collisions().unwrap();
+        assert_eq!(collisions.len(), 1);
+        assert!(!collisions[0].silent);
+        assert!(collisions[0].message.contains("• bin/tool"));
+        assert!(!collisions[0].message.contains("bin/a"));
+    }
+
+    #[test]
+    fn test_collisions_priority() {
+        let (_dir, dir) = tempdir();
+        let env = build_env(vec![
+            package(&dir, "a", 5, &[("bin/tool", "a")]),
+            package(&dir, "b", 10, &[("bin/tool", "b")]),
+        ]);
+        let collisions = env.collisions().unwrap();
+        assert_eq!(collisions.len(), 1);
+        assert!(collisions[0].silent);
+        assert!(collisions[0].message.contains("`a` (priority 5) wins"));
+    }
+
+    #[test]
+    fn test_collisions_ignore_collisions() {
+        let (_dir, dir) = tempdir();
+        let mut env = build_env(vec![
+            package(&dir, "a", 5, &[("bin/tool", "a")]),
+            package(&dir, "b", 5, &[("bin/tool", "b")]),
+        ]);
+        env.ignore_collisions = true;
+        let collisions = env.collisions().unwrap();
+        assert_eq!(collisions.len(), 1);
+        assert!(collisions[0].silent);
+    }
+
+    #[test]
+    fn test_collisions_dangling_symlinks() {
+        let (_dir, dir) = tempdir();
+        let env = build_env(vec![
+            package(&dir, "a", 5, &[("bin/tool", "->/nonexistent/a")]),
+            package(&dir, "b", 5, &[("bin/tool", "->/nonexistent/a")]),
+        ]);
+        // Dangling symlinks can't be compared, so they always collide.
+        let collisions = env.collisions().unwrap();
+        assert_eq!(collisions.len(), 1);
+        assert!(collisions[0].message.contains("• bin/tool"));
+    }
+
+    #[test]
+    fn test_collisions_symlinked_directories() {
+        let (_dir, dir) = tempdir();
+        fs_err::create_dir_all(dir.join("shared/bin")).unwrap();
+        fs_err::write(dir.join("shared/bin/tool"), "shared").unwrap();
+        let env = build_env(vec![
+            package(&dir, "a", 5, &[("bin", &format!("->{dir}/shared/bin"))]),
+            package(&dir, "b", 5, &[("bin/tool", "b")]),
+        ]);
+        let collisions = env.collisions().unwrap();
+        assert_eq!(collisions.len(), 1);
+        assert!(collisions[0].message.contains("• bin/tool"));
+    }
+
+    #[test]
+    fn test_collisions_same_file() {
+        let (_dir, dir) = tempdir();
+        fs_err::write(dir.join("tool"), "tool").unwrap();
+        let target = format!("->{dir}/tool");
+        let env = build_env(vec![
+            package(&dir, "a", 5, &[("bin/tool", &target)]),
+            package(&dir, "b", 5, &[("bin/tool", &target)]),
+        ]);
+        assert!(env.collisions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_collisions_check_contents() {
+        let (_dir, dir) = tempdir();
+        let mut env = build_env(vec![
+            package(&dir, "a", 5, &[("bin/tool", "same")]),
+            package(&dir, "b", 5, &[("bin/tool", "same")]),
+        ]);
+        assert_eq!(env.collisions().unwrap().len(), 1);
+        env.check_collision_contents = true;
+        assert!(env.collisions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_collisions_paths_to_link() {
+        let (_dir, dir) = tempdir();
+        let mut env = build_env(vec![
+            package(
+                &dir,
+                "a",
+                5,
+                &[("bin/tool", "a"), ("share/doc/README", "a")],
+            ),
+            package(
+                &dir,
+                "b",
+                5,
+                &[("bin/tool", "b"), ("share/doc/README", "b")],
+            ),
+        ]);
+        env.paths_to_link = vec!["/share/doc".to_owned()];
+        let collisions = env.collisions().unwrap();
+        assert_eq!(collisions.len(), 1);
+        assert!(collisions[0].message.contains("• share/doc/README"));
+        assert!(!collisions[0].message.contains("bin/tool"));
+    }
+
+    #[test]
+    fn test_collisions_ignored_paths() {
+        let (_dir, dir) = tempdir();
+        let env = build_env(vec![
+            package(
+                &dir,
+                "a",
+                5,
+                &[
+                    ("nix-support/setup-hook", "a"),
+                    ("lib/perl5/perllocal.pod", "a"),
+                    ("share/mime/mime.cache", "a"),
+                ],
+            ),
+            package(
+                &dir,
+                "b",
+                5,
+                &[
+                    ("nix-support/setup-hook", "b"),
+                    ("lib/perl5/perllocal.pod", "b"),
+                    ("share/mime/mime.cache", "b"),
+                ],
+            ),
+        ]);
+        assert!(env.collisions().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use miette::Context;
+use miette::IntoDiagnostic;
+
+use super::ConfigFile;
+use super::migrate::MIGRATIONS;
+use super::validate;
+use super::validate::KeyPath;
+
+/// Configuration files merged together.
+///
+/// Later files take precedence over earlier ones; tables are merged key-by-key, and any other
+/// value (including arrays) replaces the value from earlier files.
+///
+/// After the files are merged, the `[host.<hostname>]` section for the current host (if any) is
+/// merged on top, so host-specific settings take precedence over top-level settings in every
+/// file.
+#[derive(Debug, Default)]
+pub struct Layers {
+    /// The loaded files, lowest priority first.
+    paths: Vec<Utf8PathBuf>,
+    /// The hostname whose `[host.<hostname>]` section was applied, if any.
+    host: Option<String>,
+    /// Where each setting was loaded from, keyed by dotted key path.
+    origins: BTreeMap<String, LayerOrigin>,
+}
+
+/// The file (and host section) a setting was loaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerOrigin {
+    pub path: Utf8PathBuf,
+    pub host: Option<String>,
+}
+
+impl Layers {
+    /// Load and merge configuration files, lowest priority first.
+    pub fn load(
+        paths: Vec<Utf8PathBuf>,
+        hostname: Option<&str>,
+    ) -> miette::Result<(ConfigFile, Self)> {
+        let mut merged = toml::Table::new();
+        let mut origins = BTreeMap::new();
+        let mut unknown = Vec::new();
+
+        for path in &paths {
+            tracing::debug!(%path, "Loading configuration");
+            let contents = std::fs::read_to_string(path)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to read {path}"))?;
+
+            // Validate each file on its own so that diagnostics point into the right source.
+            let (_, file_unknown): (ConfigFile, _) = validate::parse(path, &contents)?;
+            unknown.extend(file_unknown);
+
+            let mut table: toml::Table = toml::from_str(&contents).into_diagnostic()?;
+            replace_deprecated(&mut table, path);
+            let origin = LayerOrigin {
+                path: path.clone(),
+                host: None,
+            };
+            merge(
+                &mut merged,
+                table,
+                &KeyPath::default(),
+                &mut origins,
+                &|_| origin.clone(),
+            );
+        }
+
+        let section = hostname.and_then(|hostname| {
+            merged
+                .get("host")
+                .and_then(|hosts| hosts.get(hostname))
+                .and_then(|section| section.as_table())
+                .map(|section| (hostname, section.clone()))
+        });
+        let host = match section {
+            Some((hostname, section)) => {
+                tracing::debug!(%hostname, "Applying host-specific configuration");
+                let file_origins = origins.clone();
+                let prefix = KeyPath::from_segments(&["host", hostname]);
+                merge(
+                    &mut merged,
+                    section,
+                    &KeyPath::default(),
+                    &mut origins,
+                    &|key| {
+                        let host_key = prefix.concat(key);
+                        LayerOrigin {
+                            path: file_origins
+                                .get(&host_key.to_string())
+                                .map(|origin| origin.path.clone())
+                                .unwrap_or_default(),
+                            host: Some(hostname.to_owned()),
+                        }
+                    },
+                );
+                Some(hostname.to_owned())
+            }
+            None => None,
+        };
+
+        let file: ConfigFile = merged
+            .try_into()
+            .into_diagnostic()
+            .wrap_err("Failed to merge configuration files")?;
+        file.check_unknown_keys(unknown)?;
+
+        Ok((
+            file,
+            Self {
+                paths,
+                host,
+                origins,
+            },
+        ))
+    }
+
+    /// Layers for files which failed to load, so that none of their settings are used.
+    pub fn unloaded(paths: Vec<Utf8PathBuf>) -> Self {
+        Self {
+            paths,
+            ..Self::default()
+        }
+    }
+
+    /// The loaded files, lowest priority first.
+    pub fn paths(&self) -> &[Utf8PathBuf] {
+        &self.paths
+    }
+
+    /// The hostname whose `[host.<hostname>]` section was applied, if any.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// Where a setting was loaded from, if it was set in a file.
+    pub fn origin(&self, key: &KeyPath) -> Option<&LayerOrigin> {
+        self.origins.get(&key.to_string())
+    }
+}
+
+/// Replace deprecated settings in a file with the settings that replace them, before merging,
+/// so that a deprecated setting in one file doesn't override its replacement in a
+/// higher-priority file.
+fn replace_deprecated(table: &mut toml::Table, path: &Utf8Path) {
+    replace_deprecated_in(table, path, &KeyPath::default());
+
+    if let Some(toml::Value::Table(hosts)) = table.get_mut("host") {
+        for (hostname, section) in hosts.iter_mut() {
+            if let toml::Value::Table(section) = section {
+                let prefix = KeyPath::from_segments(&["host", hostname]);
+                replace_deprecated_in(section, path, &prefix);
+            }
+        }
+    }
+}
+
+fn replace_deprecated_in(table: &mut toml::Table, path: &Utf8Path, prefix: &KeyPath) {
+    for migration in MIGRATIONS {
+        let (from_key, from_parents) = migration
+            .from
+            .split_last()
+            .expect("Migrations have non-empty paths");
+        let Some(value) =
+            get_table_mut(table, from_parents).and_then(|parent| parent.remove(*from_key))
+        else {
+            continue;
+        };
+
+        let from = prefix.concat(&KeyPath::from_segments(migration.from));
+        let to = prefix.concat(&KeyPath::from_segments(migration.to));
+        tracing::warn!(
+            "Config setting `{from}` in {path} is deprecated, use `{to}` instead (`npingler config migrate` can update it)"
+        );
+
+        let (to_key, to_parents) = migration
+            .to
+            .split_last()
+            .expect("Migrations have non-empty paths");
+        let mut parent = &mut *table;
+        for segment in to_parents {
+            let entry = parent
+                .entry(segment.to_string())
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            let toml::Value::Table(inner) = entry else {
+                // Validation rejects this before we get here.
+                tracing::debug!("Cannot replace `{from}` with `{to}`, which is not a table");
+                return;
+            };
+            parent = inner;
+        }
+        // Within a file, the deprecated setting takes precedence.
+        parent.insert(to_key.to_string(), value);
+    }
+}
+
+fn get_table_mut<'t>(table: &'t mut toml::Table, path: &[&str]) -> Option<&'t mut toml::Table> {
+    match path.split_first() {
+        None => Some(table),
+        Some((first, rest)) => match table.get_mut(*first)? {
+            toml::Value::Table(inner) => get_table_mut(inner, rest),
+            _ => None,
+        },
+    }
+}
+
+fn merge(
+    into: &mut toml::Table,
+    from: toml::Table,
+    parent: &KeyPath,
+    origins: &mut BTreeMap<String, LayerOrigin>,
+    origin: &impl Fn(&KeyPath) -> LayerOrigin,
+) {
+    for (key, value) in from {
+        let key_path = parent.join(&key);
+        match (into.get_mut(&key), value) {
+            (Some(toml::Value::Table(into)), toml::Value::Table(from)) => {
+                merge(into, from, &key_path, origins, origin);
+            }
+            (_, toml::Value::Table(from)) => {
+                let mut table = toml::Table::new();
+                merge(&mut table, from, &key_path, origins, origin);
+                into.insert(key, toml::Value::Table(table));
+            }
+            (_, value) => {
+                origins.insert(key_path.to_string(), origin(&key_path));
+                into.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(files: &[&str]) -> ConfigFile {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = files
+            .iter()
+            .enumerate()
+            .map(|(i, contents)| {
+                let path =
+                    Utf8PathBuf::from_path_buf(dir.path().join(format!("{i}.toml"))).unwrap();
+                fs_err::write(&path, contents).unwrap();
+                path
+            })
+            .collect();
+        Layers::load(paths, Some("grandiflora")).unwrap().0
+    }
+
+    #[test]
+    fn test_deprecated_setting_in_lower_layer() {
+        let file = load(&[
+            "profile.extra_switch_args = [\"--old\"]",
+            "nix.extra_args.\"nix-env --set\" = [\"--new\"]",
+        ]);
+        assert_eq!(
+            file.nix.extra_args.nix_env_set,
+            Some(vec!["--new".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_deprecated_setting_in_higher_layer() {
+        let file = load(&[
+            "nix.extra_args.\"nix-env --set\" = [\"--new\"]",
+            "profile.extra_switch_args = [\"--old\"]",
+        ]);
+        assert_eq!(
+            file.nix.extra_args.nix_env_set,
+            Some(vec!["--old".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_deprecated_setting_in_host_section() {
+        let file = load(&["nix.extra_args.\"nix-env --set\" = [\"--new\"]\n\
+            [host.grandiflora]\n\
+            profile.extra_switch_args = [\"--host\"]"]);
+        assert_eq!(
+            file.nix.extra_args.nix_env_set,
+            Some(vec!["--host".to_owned()])
+        );
+    }
+}
@@ -0,0 +1,314 @@
+use camino::Utf8Path;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::miette;
+use toml_edit::DocumentMut;
+use toml_edit::Item;
+use toml_edit::Key;
+use toml_edit::Table;
+use toml_edit::TableLike;
+
+use super::RunMode;
+use super::validate::KeyPath;
+use crate::format_diff;
+
+/// A deprecated setting and the setting that replaces it.
+pub struct Migration {
+    pub from: &'static [&'static str],
+    pub to: &'static [&'static str],
+}
+
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    from: &["profile", "extra_switch_args"],
+    to: &["nix", "extra_args", "nix-env --set"],
+}];
+
+/// Rewrite deprecated settings in a configuration file, preserving comments and formatting.
+///
+/// Returns `true` if the file was changed (or would be, in dry-run mode).
+pub fn migrate_file(path: &Utf8Path, run_mode: &RunMode) -> miette::Result<bool> {
+    let contents = fs_err::read_to_string(path).into_diagnostic()?;
+    let (migrated, replaced) = migrate_contents(path, &contents)?;
+
+    if replaced.is_empty() {
+        tracing::info!("No deprecated settings in {path}");
+        return Ok(false);
+    }
+
+    for (from, to) in &replaced {
+        tracing::info!("Replacing deprecated setting `{from}` with `{to}`");
+    }
+
+    let displayed = format_diff(path, &contents, &migrated);
+
+    match run_mode {
+        RunMode::Dry => {
+            tracing::info!("Would migrate {path}:\n{displayed}");
+        }
+        RunMode::Wet => {
+            tracing::info!("Migrating {path}:\n{displayed}");
+            fs_err::write(path, migrated).into_diagnostic()?;
+        }
+    }
+
+    Ok(true)
+}
+
+/// Does a configuration file contain deprecated settings?
+pub fn needs_migration(path: &Utf8Path) -> miette::Result<bool> {
+    let contents = fs_err::read_to_string(path).into_diagnostic()?;
+    let (_, replaced) = migrate_contents(path, &contents)?;
+    Ok(!replaced.is_empty())
+}
+
+/// Rewrite deprecated settings, returning the new contents and the settings which were replaced.
+fn migrate_contents(
+    path: &Utf8Path,
+    contents: &str,
+) -> miette::Result<(String, Vec<(KeyPath, KeyPath)>)> {
+    let mut document: DocumentMut = contents
+        .parse()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to parse {path}"))?;
+
+    let mut replaced = migrate_table(document.as_table_mut(), &KeyPath::default())?;
+
+    // Settings in `[host.<hostname>]` sections can be deprecated too.
+    if let Some(hosts) = document
+        .get_mut("host")
+        .and_then(|hosts| hosts.as_table_like_mut())
+    {
+        for (hostname, section) in hosts.iter_mut() {
+            if let Some(section) = section.as_table_like_mut() {
+                let prefix = KeyPath::from_segments(&["host", hostname.get()]);
+                replaced.extend(migrate_table(section, &prefix)?);
+            }
+        }
+    }
+
+    Ok((document.to_string(), replaced))
+}
+
+fn migrate_table(
+    table: &mut dyn TableLike,
+    prefix: &KeyPath,
+) -> miette::Result<Vec<(KeyPath, KeyPath)>> {
+    let mut replaced = Vec::new();
+
+    for migration in MIGRATIONS {
+        let (from_key, from_parents) = migration
+            .from
+            .split_last()
+            .expect("Migrations have non-empty paths");
+        let Some(parent) = get_table_mut(table, from_parents) else {
+            continue;
+        };
+        let Some(key) = parent.key(from_key).cloned() else {
+            continue;
+        };
+        let item = parent
+            .remove(from_key)
+            .expect("Key was just found in table");
+        let now_empty = parent.is_empty();
+        // If the old key was in a `[section]`, put the new key in a new section in the same place
+        // (rather than in dotted keys at the top of the file, above any header comments).
+        let header = get_item(table, from_parents)
+            .and_then(Item::as_table)
+            .filter(|parent| !parent.is_dotted() && !parent.is_implicit())
+            .map(|parent| Header {
+                position: parent.position(),
+                decor: now_empty.then(|| parent.decor().clone()),
+            });
+        if now_empty {
+            remove_table(table, from_parents);
+        }
+
+        let from = prefix.concat(&KeyPath::from_segments(migration.from));
+        let to = prefix.concat(&KeyPath::from_segments(migration.to));
+
+        let (to_key, to_parents) = migration
+            .to
+            .split_last()
+            .expect("Migrations have non-empty paths");
+        let parent = get_or_insert_table(table, to_parents, header)
+            .ok_or_else(|| miette!("Cannot migrate `{from}` to `{to}`, which is not a table"))?;
+        if parent.contains_key(to_key) {
+            // The deprecated setting took precedence, so this preserves the current behavior.
+            tracing::warn!("Overwriting `{to}` with the value of deprecated setting `{from}`");
+        }
+        parent.remove(to_key);
+
+        // Keep any comments attached to the old key.
+        let mut new_key = Key::new(*to_key);
+        *new_key.leaf_decor_mut() = key.leaf_decor().clone();
+        *parent.entry_format(&new_key).or_insert(Item::None) = item;
+
+        replaced.push((from, to));
+    }
+
+    Ok(replaced)
+}
+
+/// Where to put a new `[section]`.
+struct Header {
+    position: Option<isize>,
+    /// Comments from a section which was removed, to keep on the new section.
+    decor: Option<toml_edit::Decor>,
+}
+
+fn get_item<'t>(table: &'t dyn TableLike, path: &[&str]) -> Option<&'t Item> {
+    let (last, parents) = path.split_last()?;
+    let mut table = table;
+    for segment in parents {
+        table = table.get(segment)?.as_table_like()?;
+    }
+    table.get(last)
+}
+
+fn get_table_mut<'t>(table: &'t mut dyn TableLike, path: &[&str]) -> Option<&'t mut dyn TableLike> {
+    match path.split_first() {
+        None => Some(table),
+        Some((first, rest)) => get_table_mut(table.get_mut(first)?.as_table_like_mut()?, rest),
+    }
+}
+
+/// Get a nested table, creating it if it doesn't exist.
+///
+/// The first table created is a `[section]` if `header` is given, and dotted keys otherwise.
+fn get_or_insert_table<'t>(
+    table: &'t mut dyn TableLike,
+    path: &[&str],
+    header: Option<Header>,
+) -> Option<&'t mut dyn TableLike> {
+    match path.split_first() {
+        None => Some(table),
+        Some((first, rest)) => {
+            let mut header = header;
+            let item = table.entry(first).or_insert_with(|| {
+                let mut inner = Table::new();
+                match header.take() {
+                    Some(header) => {
+                        if let Some(position) = header.position {
+                            inner.set_position(position);
+                        }
+                        if let Some(decor) = header.decor {
+                            *inner.decor_mut() = decor;
+                        }
+                    }
+                    None => inner.set_dotted(true),
+                }
+                Item::Table(inner)
+            });
+            // Keys can't go directly in an implicit table (like `nix` in `[nix.extra_args]`), so
+            // put the header on the next table instead.
+            let header = header.filter(|_| item.as_table().is_some_and(Table::is_implicit));
+            get_or_insert_table(item.as_table_like_mut()?, rest, header)
+        }
+    }
+}
+
+/// Remove an empty nested table, and any parent tables left empty.
+fn remove_table(table: &mut dyn TableLike, path: &[&str]) {
+    if let Some((last, parents)) = path.split_last()
+        && let Some(parent) = get_table_mut(table, parents)
+    {
+        parent.remove(last);
+        if parent.is_empty() {
+            remove_table(table, parents);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn migrate(contents: &str) -> String {
+        migrate_contents(Utf8Path::new("config.toml"), contents)
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn test_migrate_section_keeps_header_comment() {
+        assert_eq!(
+            migrate(
+                "# top comment\n\
+                [channels]\n\
+                pin_root = true\n\
+                \n\
+                [profile]\n\
+                # deprecated\n\
+                extra_switch_args = [\"--foo\"]  # trailing\n\
+                \n\
+                [log]\n\
+                filter = \"info\"\n"
+            ),
+            "# top comment\n\
+            [channels]\n\
+            pin_root = true\n\
+            \n\
+            [nix]\n\
+            # deprecated\n\
+            extra_args.\"nix-env --set\" = [\"--foo\"]  # trailing\n\
+            \n\
+            [log]\n\
+            filter = \"info\"\n"
+        );
+    }
+
+    #[test]
+    fn test_migrate_section_with_other_keys() {
+        assert_eq!(
+            migrate(
+                "[profile]\n\
+                extra_switch_args = [\"--foo\"]\n\
+                system = false\n"
+            ),
+            "[profile]\n\
+            system = false\n\
+            \n\
+            [nix]\n\
+            extra_args.\"nix-env --set\" = [\"--foo\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_migrate_into_existing_table() {
+        assert_eq!(
+            migrate(
+                "profile.extra_switch_args = [\"--foo\"]\n\
+                profile.system = false\n\
+                \n\
+                [nix]\n\
+                offline = false\n"
+            ),
+            "profile.system = false\n\
+            \n\
+            [nix]\n\
+            offline = false\n\
+            extra_args.\"nix-env --set\" = [\"--foo\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_migrate_host_section() {
+        assert_eq!(
+            migrate(
+                "[host.grandiflora]\n\
+                profile.extra_switch_args = [\"--bar\"]\n"
+            ),
+            "[host.grandiflora]\n\
+            nix.extra_args.\"nix-env --set\" = [\"--bar\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_migrate_nothing() {
+        let contents = "# comment\n[nix]\noffline = true\n";
+        let (migrated, replaced) =
+            migrate_contents(Utf8Path::new("config.toml"), contents).unwrap();
+        assert_eq!(migrated, contents);
+        assert!(replaced.is_empty());
+    }
+}
@@ -0,0 +1,735 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::miette;
+
+use crate::clap::ShellWords;
+use crate::cli::Args;
+use crate::cli::SwitchArgs;
+use crate::directories::ProjectPaths;
+use crate::format_bulleted_list;
+use crate::nix::Nix;
+use crate::privilege::Privilege;
+use crate::privilege::PrivilegeCommand;
+
+mod layers;
+use layers::Layers;
+
+mod migrate;
+
+mod show;
+
+mod validate;
+pub use validate::UnknownKey;
+use validate::UnknownKeys;
+use validate::UnknownKeysError;
+
+pub const DEFAULT_CONFIG: &str = include_str!("../../config.toml");
+
+const DEFAULT_ROOT_REGISTRY_PATH: &str = "/etc/nix/registry.json";
+const DEFAULT_ROOT_CHANNELS_PROFILE: &str = "/nix/var/nix/profiles/per-user/root/channels";
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum LogFilter {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct Log {
+    #[serde(alias = "filter")]
+    filters: Option<LogFilter>,
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct Registry {
+    pin_root: Option<bool>,
+    root_path: Option<String>,
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct Channels {
+    pin_root: Option<bool>,
+    root_profile: Option<String>,
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct Profile {
+    file: Option<String>,
+    system: Option<bool>,
+    strict_collisions: Option<bool>,
+    /// Deprecated; replaced with `nix.extra_args."nix-env --set"` as each file is loaded.
+    #[expect(dead_code)]
+    extra_switch_args: Option<Vec<String>>,
+    diff_derivations: Option<Vec<String>>,
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct PrivilegeConfig {
+    command: Option<PrivilegeCommand>,
+}
+
+#[derive(serde::Deserialize, Default, Debug, Clone)]
+pub struct NixConfig {
+    #[serde(default)]
+    extra_args: NixExtraArgs,
+    offline: Option<bool>,
+}
+
+#[derive(serde::Deserialize, Default, Debug, Clone)]
+pub struct NixExtraArgs {
+    nix: Option<Vec<String>>,
+    #[serde(rename = "nix build")]
+    build: Option<Vec<String>>,
+    #[serde(rename = "nix eval")]
+    eval: Option<Vec<String>>,
+    #[serde(rename = "nix-env --set")]
+    nix_env_set: Option<Vec<String>>,
+}
+
+impl NixExtraArgs {
+    pub fn nix(&self) -> &[String] {
+        self.nix.as_deref().unwrap_or_default()
+    }
+
+    pub fn build(&self) -> &[String] {
+        self.build.as_deref().unwrap_or_default()
+    }
+
+    pub fn eval(&self) -> &[String] {
+        self.eval.as_deref().unwrap_or_default()
+    }
+
+    pub fn nix_env_set(&self) -> &[String] {
+        self.nix_env_set.as_deref().unwrap_or_default()
+    }
+}
+
+/// Maps hostnames matching a pattern to a `npingler.<attr>` attribute name.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct HostAlias {
+    /// A glob pattern, like `*.corp.example.com`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    glob: Option<String>,
+    /// A regular expression, like `^ci-[0-9]+$`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    regex: Option<String>,
+    attr: String,
+}
+
+impl HostAlias {
+    fn matches(&self, hostname: &str) -> miette::Result<bool> {
+        match (&self.glob, &self.regex) {
+            (Some(glob), None) => Ok(globset::Glob::new(glob)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Invalid `hosts` glob: {glob}"))?
+                .compile_matcher()
+                .is_match(hostname)),
+            (None, Some(regex)) => Ok(regex::Regex::new(regex)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Invalid `hosts` regex: {regex}"))?
+                .is_match(hostname)),
+            _ => Err(miette!(
+                "Each `hosts` entry must have exactly one of `glob` or `regex`, but the entry for `{}` has {}",
+                self.attr,
+                if self.glob.is_some() {
+                    "both"
+                } else {
+                    "neither"
+                }
+            )),
+        }
+    }
+}
+
+/// Configuration loaded from a file.
+#[derive(serde::Deserialize, Default)]
+pub struct ConfigFile {
+    #[serde(default)]
+    unknown_keys: UnknownKeys,
+    #[serde(default)]
+    log: Log,
+    file: Option<String>,
+    #[serde(default)]
+    profile: Profile,
+    #[serde(default)]
+    registry: Registry,
+    #[serde(default)]
+    channels: Channels,
+    #[serde(default)]
+    nix: NixConfig,
+    #[serde(default)]
+    privilege: PrivilegeConfig,
+    #[serde(default)]
+    hosts: Vec<HostAlias>,
+    /// Per-host settings, keyed by hostname, which override the top-level settings.
+    #[serde(default)]
+    host: BTreeMap<String, ConfigFile>,
+}
+
+impl ConfigFile {
+    /// Report unknown keys according to the `unknown_keys` setting.
+    fn check_unknown_keys(&self, unknown: Vec<UnknownKey>) -> miette::Result<()> {
+        if unknown.is_empty() {
+            return Ok(());
+        }
+
+        match self.unknown_keys {
+            UnknownKeys::Ignore => {}
+            UnknownKeys::Warn => {
+                for key in unknown {
+                    tracing::warn!("{:?}", miette::Report::new(key));
+                }
+            }
+            UnknownKeys::Error => {
+                return Err(UnknownKeysError::new(unknown).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the keys in the file which aren't used by any setting.
+    pub fn unknown_keys(path: &Utf8Path) -> miette::Result<Vec<UnknownKey>> {
+        let contents = std::fs::read_to_string(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read {path}"))?;
+
+        let (_, unknown): (Self, _) = validate::parse(path, &contents)?;
+        Ok(unknown)
+    }
+}
+
+/// Where the hostname used to select a configuration came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostnameSource {
+    Flag,
+    Env,
+    Gethostname,
+}
+
+impl Display for HostnameSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostnameSource::Flag => write!(f, "`--hostname`"),
+            HostnameSource::Env => write!(f, "`$HOSTNAME`"),
+            HostnameSource::Gethostname => write!(f, "the system hostname"),
+        }
+    }
+}
+
+pub enum RunMode {
+    Dry,
+    /// Well do YOU have a better name for it?
+    Wet,
+}
+
+pub struct Config {
+    layers: Layers,
+    project_paths: ProjectPaths,
+    file: ConfigFile,
+    args: Args,
+    switch_args: SwitchArgs,
+}
+
+impl Config {
+    pub fn from_args(args: Args) -> miette::Result<Self> {
+        let (config, paths, hostname) = Self::unloaded(args)?;
+        let (file, layers) = Layers::load(paths, hostname.as_deref())?;
+        Ok(Self {
+            file,
+            layers,
+            ..config
+        })
+    }
+
+    /// Like [`Config::from_args`], but if the configuration files fail to load, use the default
+    /// settings and return the error, so that `npingler doctor` can diagnose it.
+    pub fn from_args_lenient(args: Args) -> miette::Result<(Self, Option<miette::Report>)> {
+        let (config, paths, hostname) = Self::unloaded(args)?;
+        match Layers::load(paths.clone(), hostname.as_deref()) {
+            Ok((file, layers)) => Ok((
+                Self {
+                    file,
+                    layers,
+                    ..config
+                },
+                None,
+            )),
+            Err(err) => Ok((
+                Self {
+                    layers: Layers::unloaded(paths),
+                    ..config
+                },
+                Some(err),
+            )),
+        }
+    }
+
+    /// Get the configuration with default settings, the existing configuration files to load
+    /// (lowest priority first), and the hostname to load settings for.
+    fn unloaded(args: Args) -> miette::Result<(Self, Vec<Utf8PathBuf>, Option<String>)> {
+        let project_paths = ProjectPaths::new()?;
+        let paths = args.config_paths(&project_paths)?;
+
+        // This is really silly.
+        let switch_args = match &args.command {
+            crate::cli::Command::Update { switch_args, .. } => switch_args.clone(),
+            crate::cli::Command::Switch { switch_args, .. } => switch_args.clone(),
+            crate::cli::Command::Config(crate::cli::ConfigCommand::Show { switch_args }) => {
+                switch_args.clone()
+            }
+            crate::cli::Command::Config(_) => SwitchArgs::default(),
+            crate::cli::Command::Build { switch_args } => switch_args.clone(),
+            crate::cli::Command::Bundle { switch_args, .. } => switch_args.clone(),
+            crate::cli::Command::Add { switch_args, .. } => switch_args.clone(),
+            crate::cli::Command::Remove { switch_args, .. } => switch_args.clone(),
+            crate::cli::Command::Search { switch_args, .. } => switch_args.clone(),
+            crate::cli::Command::Which { switch_args, .. } => switch_args.clone(),
+            crate::cli::Command::List { switch_args, .. } => switch_args.clone(),
+            crate::cli::Command::Status { switch_args } => switch_args.clone(),
+            crate::cli::Command::Hosts { switch_args } => switch_args.clone(),
+            crate::cli::Command::Profiles(crate::cli::ProfilesCommand::List { switch_args }) => {
+                switch_args.clone()
+            }
+            crate::cli::Command::Doctor { switch_args } => switch_args.clone(),
+            crate::cli::Command::Init { switch_args, .. } => switch_args.clone(),
+            crate::cli::Command::Util(util_command) => match util_command {
+                crate::cli::UtilCommand::GenerateCompletions { .. } => SwitchArgs::default(),
+                #[cfg(feature = "clap_mangen")]
+                crate::cli::UtilCommand::GenerateManPages { .. } => SwitchArgs::default(),
+            },
+        };
+
+        tracing::trace!(?paths, "Looking for configuration files");
+        let mut existing = Vec::with_capacity(paths.len());
+        // `paths` is in order of decreasing priority, but we want to load the highest-priority
+        // file last so that its settings win.
+        for path in paths.into_iter().rev() {
+            if path
+                .try_exists()
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to check if configuration path exists: {path}"))?
+            {
+                existing.push(path);
+            }
+        }
+
+        if existing.is_empty() {
+            tracing::debug!("No configuration file found");
+        }
+
+        let hostname = Self::resolve_hostname(&switch_args)
+            .inspect_err(|err| tracing::debug!("Not loading host-specific configuration: {err}"))
+            .ok();
+        Ok((
+            Self {
+                layers: Layers::default(),
+                project_paths,
+                file: ConfigFile::default(),
+                args,
+                switch_args,
+            },
+            existing,
+            hostname,
+        ))
+    }
+
+    pub fn command(&self) -> &crate::cli::Command {
+        &self.args.command
+    }
+
+    /// The store path given with `switch --store-path`, if any.
+    pub fn store_path(&self) -> Option<&Utf8Path> {
+        match &self.args.command {
+            crate::cli::Command::Switch { store_path, .. } => store_path.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The pins file given with `switch --pins`, if any.
+    pub fn pins_file(&self) -> Option<&Utf8Path> {
+        match &self.args.command {
+            crate::cli::Command::Switch { pins, .. } => pins.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The bundle given with `switch --from-bundle`, if any.
+    pub fn bundle(&self) -> Option<&Utf8Path> {
+        match &self.args.command {
+            crate::cli::Command::Switch { from_bundle, .. } => from_bundle.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The loaded configuration files, lowest priority first.
+    pub fn paths(&self) -> &[Utf8PathBuf] {
+        self.layers.paths()
+    }
+
+    pub fn project_paths(&self) -> &ProjectPaths {
+        &self.project_paths
+    }
+
+    /// Was `--verbose` or `--debug` given?
+    pub fn verbose(&self) -> bool {
+        self.args.log.verbose || self.args.log.debug
+    }
+
+    pub fn log_filter(&self) -> String {
+        let mut ret = String::new();
+        match &self.file.log.filters {
+            Some(LogFilter::One(filter)) => {
+                ret.push_str(filter);
+            }
+            Some(LogFilter::Many(filters)) => {
+                ret.push_str(&filters.join(","));
+            }
+            None => {}
+        }
+
+        if let Some(filter) = &self.args.log_filter() {
+            ret.push(',');
+            ret.push_str(filter);
+        }
+
+        if ret.is_empty() {
+            ret.push_str(crate::tracing::DEFAULT_FILTER);
+        }
+
+        ret
+    }
+
+    pub fn nix_file(&self) -> miette::Result<Utf8PathBuf> {
+        fn file_does_not_exist(description: &str, file: &str) -> miette::Report {
+            miette!(
+                "{description} does not exist or (if it's a directory) contain a `default.nix`: {file}"
+            )
+        }
+
+        if let Some(file) = &self.args.file {
+            return Self::resolve_nix_file(&self.project_paths.expand_tilde(file)?)?
+                .ok_or_else(|| file_does_not_exist("`--file`", file));
+        }
+
+        if let Some(file) = &self.file.file {
+            return Self::resolve_nix_file(&self.project_paths.expand_tilde(file)?)?
+                .ok_or_else(|| file_does_not_exist("`file` setting in config", file));
+        }
+
+        let mut paths = self.project_paths.nix_paths()?;
+
+        if let Some(path) = self.layers.paths().last() {
+            paths.push(
+                path.parent()
+                    .ok_or_else(|| miette!("Configuration file has no parent directory: {path}"))?
+                    .to_path_buf(),
+            );
+        }
+
+        for path in &paths {
+            if let Some(path) = Self::resolve_nix_file(path)? {
+                return Ok(path);
+            }
+        }
+
+        Err(miette!(
+            "Unable to find npingler `default.nix`. I looked in these paths:\n{}",
+            format_bulleted_list(&paths)
+        ))
+    }
+
+    fn resolve_nix_file(path: &Utf8Path) -> miette::Result<Option<Utf8PathBuf>> {
+        match crate::fs::exists_metadata(path).into_diagnostic()? {
+            Some(metadata) => {
+                if metadata.is_dir() {
+                    let default_nix = path.join("default.nix");
+                    match crate::fs::exists_metadata(&default_nix).into_diagnostic()? {
+                        Some(_) => Ok(Some(path.to_owned())),
+                        None => Ok(None),
+                    }
+                } else {
+                    Ok(Some(path.to_owned()))
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn hostname(&self) -> miette::Result<String> {
+        Self::resolve_hostname(&self.switch_args)
+    }
+
+    /// Map a hostname to a `npingler.<attr>` attribute name with the `hosts` setting.
+    ///
+    /// The first matching entry wins.
+    pub fn host_alias(&self, hostname: &str) -> miette::Result<Option<&str>> {
+        for alias in &self.file.hosts {
+            if alias.matches(hostname)? {
+                tracing::debug!(%hostname, attr = %alias.attr, "Hostname matched `hosts` entry");
+                return Ok(Some(&alias.attr));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn hostname_source(&self) -> HostnameSource {
+        match &self.switch_args.hostname {
+            None => HostnameSource::Gethostname,
+            Some(_) if self.args.set_from_env("hostname") => HostnameSource::Env,
+            Some(_) => HostnameSource::Flag,
+        }
+    }
+
+    fn resolve_hostname(switch_args: &SwitchArgs) -> miette::Result<String> {
+        match &switch_args.hostname {
+            Some(hostname) => Ok(hostname.clone()),
+            None => gethostname::gethostname()
+                .into_string()
+                .map_err(|s| miette!("Hostname is not UTF-8: {s:?}")),
+        }
+    }
+
+    pub fn nix_profile(&self, nix: &Nix) -> miette::Result<Utf8PathBuf> {
+        if let Some(profile) = self.switch_args.profile.profile.as_deref() {
+            return Ok(profile.to_path_buf());
+        }
+
+        if self.system_profile() {
+            if Utf8Path::new("/etc/NIXOS").exists() {
+                return Err(miette!(
+                    help = "Add packages to `environment.systemPackages` in your NixOS \
+                        configuration instead",
+                    "`--system` is not supported on NixOS"
+                ));
+            }
+            return Ok(ProjectPaths::system_nix_profile());
+        }
+
+        if let Some(profile) = self.file.profile.file.as_deref() {
+            return self.project_paths.expand_tilde(profile);
+        }
+
+        self.project_paths.nix_profile(nix)
+    }
+
+    /// Create the user's Nix profile links if they don't exist, unless another profile is
+    /// configured.
+    pub fn create_nix_profile_links(&self, nix: &Nix) -> miette::Result<()> {
+        if self.switch_args.profile.profile.is_some()
+            || self.system_profile()
+            || self.file.profile.file.is_some()
+        {
+            return Ok(());
+        }
+        self.project_paths.create_nix_profile_links(nix)
+    }
+
+    /// Get the profile link for a named profile.
+    pub fn named_nix_profile(&self, name: &str) -> miette::Result<Utf8PathBuf> {
+        self.project_paths
+            .named_nix_profile(name, self.system_profile())
+    }
+
+    /// Are we managing the default system-wide profile rather than the user's profile?
+    pub fn system_profile(&self) -> bool {
+        self.switch_args
+            .profile
+            .system
+            .then_some(true)
+            .or(self.file.profile.system)
+            .unwrap_or(false)
+    }
+
+    /// Should files in a profile's packages which collide, but where one package silently wins
+    /// by priority, be treated as errors?
+    pub fn strict_collisions(&self) -> bool {
+        self.switch_args
+            .profile
+            .strict_collisions
+            .then_some(true)
+            .or(self.file.profile.strict_collisions)
+            .unwrap_or(false)
+    }
+
+    /// The profiles selected with `--profile-name`, or an empty slice to select all profiles.
+    pub fn profile_names(&self) -> &[String] {
+        &self.switch_args.profile.profile_names
+    }
+
+    pub fn nix(&self) -> miette::Result<Nix> {
+        fn massage_args(
+            cli: &Option<ShellWords>,
+            file: &Option<Vec<String>>,
+        ) -> Option<Vec<String>> {
+            cli.clone().map(|args| args.into()).or_else(|| file.clone())
+        }
+
+        let extra_args = NixExtraArgs {
+            nix: massage_args(
+                &self.switch_args.nix.extra_nix_args,
+                &self.file.nix.extra_args.nix,
+            ),
+            build: massage_args(
+                &self.switch_args.nix.extra_nix_build_args,
+                &self.file.nix.extra_args.build,
+            ),
+            eval: massage_args(
+                &self.switch_args.nix.extra_nix_eval_args,
+                &self.file.nix.extra_args.eval,
+            ),
+            nix_env_set: {
+                if let Some(args) = self.switch_args.profile.extra_switch_args.clone() {
+                    tracing::warn!(
+                        "--extra-switch-args is deprecated, use --extra-nix-env-set-args instead"
+                    );
+                    Some(args.into())
+                } else if let Some(args) = self.switch_args.nix.extra_nix_env_set_args.clone() {
+                    Some(args.into())
+                } else {
+                    self.file.nix.extra_args.nix_env_set.clone()
+                }
+            },
+        };
+
+        Nix::new(extra_args, self.offline())
+    }
+
+    /// Should Nix avoid using the network?
+    pub fn offline(&self) -> bool {
+        self.switch_args
+            .nix
+            .offline
+            .then_some(true)
+            .or(self.file.nix.offline)
+            .unwrap_or(false)
+    }
+
+    pub fn privilege(&self) -> Privilege {
+        Privilege::new(
+            self.switch_args
+                .privilege
+                .privilege_command
+                .clone()
+                .or_else(|| self.file.privilege.command.clone())
+                .unwrap_or_default(),
+        )
+    }
+
+    pub fn channels_pin_root(&self) -> bool {
+        self.switch_args
+            .channel
+            .pin_channels_root
+            .or(self.file.channels.pin_root)
+            .unwrap_or(false)
+    }
+
+    pub fn channels_root_profile(&self) -> miette::Result<Utf8PathBuf> {
+        self.switch_args
+            .channel
+            .root_profile
+            .clone()
+            .map(Ok)
+            .or(self
+                .file
+                .channels
+                .root_profile
+                .as_deref()
+                .map(|profile| self.project_paths.expand_tilde(profile)))
+            .unwrap_or_else(|| Ok(Utf8Path::new(DEFAULT_ROOT_CHANNELS_PROFILE).to_owned()))
+    }
+
+    pub fn root_registry_path(&self) -> miette::Result<Utf8PathBuf> {
+        if let Some(profile) = &self.switch_args.registry.root_registry_path {
+            return Ok(profile.clone());
+        }
+
+        if let Some(profile) = &self.file.registry.root_path {
+            return self.project_paths.expand_tilde(profile);
+        }
+
+        Ok(Utf8Path::new(DEFAULT_ROOT_REGISTRY_PATH).to_owned())
+    }
+
+    pub fn registry_pin_root(&self) -> bool {
+        self.switch_args
+            .registry
+            .pin_registry_root
+            .or(self.file.registry.pin_root)
+            .unwrap_or(false)
+    }
+
+    /// Write the default config file.
+    pub fn init(output: Option<&str>) -> miette::Result<()> {
+        let path: Utf8PathBuf = match output {
+            Some(path) => {
+                if path == "-" {
+                    print!("{}", DEFAULT_CONFIG);
+                    return Ok(());
+                } else {
+                    path.to_owned().into()
+                }
+            }
+            None => ProjectPaths::new()?.default_config_path()?,
+        };
+
+        if path.exists() {
+            return Err(miette!("Path already exists: {path}"));
+        }
+        fs_err::write(path, DEFAULT_CONFIG).into_diagnostic()?;
+
+        Ok(())
+    }
+
+    /// Rewrite deprecated settings in the loaded configuration files.
+    pub fn migrate(&self) -> miette::Result<()> {
+        if self.paths().is_empty() {
+            tracing::info!("No configuration files found");
+        }
+
+        // System configuration files usually aren't writable, so only migrate the user's own
+        // file unless one is given explicitly.
+        let user_config = self.project_paths.default_config_path()?;
+        for path in self.paths() {
+            if self.args.config.is_none() && *path != user_config {
+                if migrate::needs_migration(path)? {
+                    tracing::warn!(
+                        "Not migrating system configuration file {path}; use `npingler --config {path} config migrate` to migrate it"
+                    );
+                }
+                continue;
+            }
+            migrate::migrate_file(path, &self.run_mode())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn run_mode(&self) -> RunMode {
+        match self.args.dry {
+            true => RunMode::Dry,
+            false => RunMode::Wet,
+        }
+    }
+
+    pub fn diff_derivations(&self) -> miette::Result<Option<Vec<String>>> {
+        Ok(self
+            .switch_args
+            .profile
+            .diff_derivations
+            .as_deref()
+            .map(|arg| {
+                shell_words::split(arg).into_diagnostic().wrap_err_with(|| {
+                    format!("Failed to shell-split `--diff-derivations` arg: {arg}")
+                })
+            })
+            .transpose()?
+            .or_else(|| self.file.profile.diff_derivations.clone()))
+    }
+}
@@ -0,0 +1,348 @@
+use std::fmt::Display;
+
+use itertools::Itertools;
+
+use super::Config;
+use super::DEFAULT_ROOT_CHANNELS_PROFILE;
+use super::DEFAULT_ROOT_REGISTRY_PATH;
+use super::LogFilter;
+use super::layers::LayerOrigin;
+use super::validate::KeyPath;
+
+/// Where a setting's value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    File(LayerOrigin),
+    Flag(&'static str),
+    Env(&'static str),
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(LayerOrigin { path, host: None }) => write!(f, "{path}"),
+            Origin::File(LayerOrigin {
+                path,
+                host: Some(host),
+            }) => write!(f, "{path} [host.{}]", KeyPath::from_segments(&[host])),
+            Origin::Flag(flag) => write!(f, "{flag}"),
+            Origin::Env(env) => write!(f, "${env}"),
+        }
+    }
+}
+
+/// An effective configuration setting.
+struct Setting {
+    key: KeyPath,
+    value: Option<toml::Value>,
+    origin: Origin,
+}
+
+impl Display for Setting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{} = {value} # {}", self.key, self.origin),
+            None => write!(f, "# {} is not set", self.key),
+        }
+    }
+}
+
+fn strings(values: &[String]) -> toml::Value {
+    toml::Value::from(values.to_vec())
+}
+
+impl Config {
+    /// Determine if a command-line argument was given by a flag or an environment variable.
+    fn cli_origin(&self, id: &str, flag: &'static str, env: Option<&'static str>) -> Origin {
+        match env {
+            Some(env) if self.args.set_from_env(id) => Origin::Env(env),
+            _ => Origin::Flag(flag),
+        }
+    }
+
+    fn setting(
+        &self,
+        key: &[&str],
+        cli: Option<(toml::Value, Origin)>,
+        file: Option<toml::Value>,
+        default: Option<toml::Value>,
+    ) -> Setting {
+        let key = KeyPath::from_segments(key);
+
+        if let Some((value, origin)) = cli {
+            return Setting {
+                key,
+                value: Some(value),
+                origin,
+            };
+        }
+
+        if let Some(value) = file
+            && let Some(origin) = self.layers.origin(&key)
+        {
+            return Setting {
+                key,
+                value: Some(value),
+                origin: Origin::File(origin.clone()),
+            };
+        }
+
+        Setting {
+            key,
+            value: default,
+            origin: Origin::Default,
+        }
+    }
+
+    /// Display the effective configuration and where each setting came from.
+    pub fn show(&self) -> String {
+        let file = &self.file;
+        let switch_args = &self.switch_args;
+
+        let log_cli_origin = if self.args.log.debug {
+            Origin::Flag("--debug")
+        } else if self.args.log.verbose {
+            Origin::Flag("--verbose")
+        } else {
+            self.cli_origin("log_filter", "--log-filter", Some("NPINGLER_LOG"))
+        };
+
+        let nix_env_set = if let Some(args) = &switch_args.profile.extra_switch_args {
+            Some((strings(args), Origin::Flag("--extra-switch-args")))
+        } else {
+            switch_args
+                .nix
+                .extra_nix_env_set_args
+                .as_ref()
+                .map(|args| (strings(args), Origin::Flag("--extra-nix-env-set-args")))
+        };
+        // The deprecated `profile.extra_switch_args` setting is replaced with this one as each
+        // file is loaded.
+        let nix_env_set = self.setting(
+            &["nix", "extra_args", "nix-env --set"],
+            nix_env_set,
+            file.nix.extra_args.nix_env_set.as_deref().map(strings),
+            None,
+        );
+
+        let settings = [
+            self.setting(
+                &["unknown_keys"],
+                None,
+                Some(toml::Value::from(file.unknown_keys.to_string())),
+                Some(toml::Value::from(file.unknown_keys.to_string())),
+            ),
+            self.setting(
+                &["log", "filters"],
+                self.args.log_filter().map(|_| {
+                    let filter = self.log_filter();
+                    (toml::Value::from(filter.trim_matches(',')), log_cli_origin)
+                }),
+                file.log.filters.as_ref().map(|filters| match filters {
+                    LogFilter::One(filter) => toml::Value::from(filter.clone()),
+                    LogFilter::Many(filters) => strings(filters),
+                }),
+                Some(toml::Value::from(crate::tracing::DEFAULT_FILTER)),
+            ),
+            self.setting(
+                &["file"],
+                self.args.file.as_ref().map(|path| {
+                    (
+                        toml::Value::from(path.clone()),
+                        self.cli_origin("file", "--file", None),
+                    )
+                }),
+                file.file.clone().map(toml::Value::from),
+                None,
+            ),
+            self.setting(
+                &["profile", "file"],
+                switch_args.profile.profile.as_ref().map(|path| {
+                    (
+                        toml::Value::from(path.to_string()),
+                        self.cli_origin("profile", "--profile", Some("NIX_PROFILE")),
+                    )
+                }),
+                file.profile.file.clone().map(toml::Value::from),
+                None,
+            ),
+            self.setting(
+                &["profile", "system"],
+                switch_args
+                    .profile
+                    .system
+                    .then(|| (toml::Value::from(true), Origin::Flag("--system"))),
+                file.profile.system.map(toml::Value::from),
+                Some(toml::Value::from(false)),
+            ),
+            self.setting(
+                &["profile", "strict_collisions"],
+                switch_args
+                    .profile
+                    .strict_collisions
+                    .then(|| (toml::Value::from(true), Origin::Flag("--strict-collisions"))),
+                file.profile.strict_collisions.map(toml::Value::from),
+                Some(toml::Value::from(false)),
+            ),
+            self.setting(
+                &["profile", "diff_derivations"],
+                switch_args
+                    .profile
+                    .diff_derivations
+                    .as_ref()
+                    .map(|command| {
+                        (
+                            toml::Value::from(command.clone()),
+                            Origin::Flag("--diff-derivations"),
+                        )
+                    }),
+                file.profile.diff_derivations.as_deref().map(strings),
+                None,
+            ),
+            self.setting(
+                &["registry", "pin_root"],
+                switch_args
+                    .registry
+                    .pin_registry_root
+                    .map(|pin| (toml::Value::from(pin), Origin::Flag("--pin-registry-root"))),
+                file.registry.pin_root.map(toml::Value::from),
+                Some(toml::Value::from(false)),
+            ),
+            self.setting(
+                &["registry", "root_path"],
+                switch_args
+                    .registry
+                    .root_registry_path
+                    .as_ref()
+                    .map(|path| {
+                        (
+                            toml::Value::from(path.to_string()),
+                            self.cli_origin(
+                                "root_registry_path",
+                                "--root-registry-path",
+                                Some("ROOT_NIX_REGISTRY"),
+                            ),
+                        )
+                    }),
+                file.registry.root_path.clone().map(toml::Value::from),
+                Some(toml::Value::from(DEFAULT_ROOT_REGISTRY_PATH)),
+            ),
+            self.setting(
+                &["channels", "pin_root"],
+                switch_args
+                    .channel
+                    .pin_channels_root
+                    .map(|pin| (toml::Value::from(pin), Origin::Flag("--pin-channels-root"))),
+                file.channels.pin_root.map(toml::Value::from),
+                Some(toml::Value::from(false)),
+            ),
+            self.setting(
+                &["channels", "root_profile"],
+                switch_args.channel.root_profile.as_ref().map(|path| {
+                    (
+                        toml::Value::from(path.to_string()),
+                        self.cli_origin("root_profile", "--root-profile", Some("ROOT_NIX_PROFILE")),
+                    )
+                }),
+                file.channels.root_profile.clone().map(toml::Value::from),
+                Some(toml::Value::from(DEFAULT_ROOT_CHANNELS_PROFILE)),
+            ),
+            self.setting(
+                &["nix", "extra_args", "nix"],
+                switch_args
+                    .nix
+                    .extra_nix_args
+                    .as_ref()
+                    .map(|args| (strings(args), Origin::Flag("--extra-nix-args"))),
+                file.nix.extra_args.nix.as_deref().map(strings),
+                None,
+            ),
+            self.setting(
+                &["nix", "extra_args", "nix build"],
+                switch_args
+                    .nix
+                    .extra_nix_build_args
+                    .as_ref()
+                    .map(|args| (strings(args), Origin::Flag("--extra-nix-build-args"))),
+                file.nix.extra_args.build.as_deref().map(strings),
+                None,
+            ),
+            self.setting(
+                &["nix", "extra_args", "nix eval"],
+                switch_args
+                    .nix
+                    .extra_nix_eval_args
+                    .as_ref()
+                    .map(|args| (strings(args), Origin::Flag("--extra-nix-eval-args"))),
+                file.nix.extra_args.eval.as_deref().map(strings),
+                None,
+            ),
+            nix_env_set,
+            self.setting(
+                &["nix", "offline"],
+                switch_args
+                    .nix
+                    .offline
+                    .then(|| (toml::Value::from(true), Origin::Flag("--offline"))),
+                file.nix.offline.map(toml::Value::from),
+                Some(toml::Value::from(false)),
+            ),
+            self.setting(
+                &["hosts"],
+                None,
+                (!file.hosts.is_empty())
+                    .then(|| toml::Value::try_from(&file.hosts).ok())
+                    .flatten(),
+                None,
+            ),
+            self.setting(
+                &["privilege", "command"],
+                switch_args
+                    .privilege
+                    .privilege_command
+                    .as_ref()
+                    .map(|command| {
+                        (
+                            toml::Value::from(command.to_string()),
+                            Origin::Flag("--privilege-command"),
+                        )
+                    }),
+                file.privilege
+                    .command
+                    .as_ref()
+                    .map(|command| toml::Value::from(command.to_string())),
+                Some(toml::Value::from("sudo")),
+            ),
+        ];
+
+        let mut ret = String::new();
+        if self.layers.paths().is_empty() {
+            ret.push_str("# No configuration files found\n");
+        } else {
+            ret.push_str("# Loaded configuration files, lowest priority first:\n");
+            for path in self.layers.paths() {
+                ret.push_str(&format!("# - {path}\n"));
+            }
+        }
+        match self.layers.host() {
+            Some(host) => ret.push_str(&format!(
+                "# Applied host section: [host.{}]\n",
+                KeyPath::from_segments(&[host])
+            )),
+            None if !self.file.host.is_empty() => ret.push_str(&format!(
+                "# No host section applied; available sections: {}\n",
+                self.file
+                    .host
+                    .keys()
+                    .map(|host| format!("[host.{}]", KeyPath::from_segments(&[host])))
+                    .join(", ")
+            )),
+            None => {}
+        }
+        ret.push_str(&settings.iter().join("\n"));
+        ret.push('\n');
+        ret
+    }
+}
@@ -0,0 +1,316 @@
+use std::fmt::Display;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use miette::NamedSource;
+use miette::SourceSpan;
+use serde::de::DeserializeOwned;
+use toml::de::DeTable;
+use toml::de::DeValue;
+
+/// Every key `npingler` understands in `config.toml`, used to suggest corrections for typos.
+///
+/// Keep this in sync with [`super::ConfigFile`].
+const KNOWN_KEYS: &[&[&str]] = &[
+    &["unknown_keys"],
+    &["log", "filters"],
+    &["log", "filter"],
+    &["file"],
+    &["profile", "file"],
+    &["profile", "system"],
+    &["profile", "strict_collisions"],
+    &["profile", "extra_switch_args"],
+    &["profile", "diff_derivations"],
+    &["registry", "pin_root"],
+    &["registry", "root_path"],
+    &["channels", "pin_root"],
+    &["channels", "root_profile"],
+    &["nix", "extra_args", "nix"],
+    &["nix", "extra_args", "nix build"],
+    &["nix", "extra_args", "nix eval"],
+    &["nix", "extra_args", "nix-env --set"],
+    &["nix", "offline"],
+    &["privilege", "command"],
+    &["hosts"],
+    &["host"],
+];
+
+/// What to do when a configuration file contains keys we don't recognize.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownKeys {
+    Ignore,
+    #[default]
+    Warn,
+    Error,
+}
+
+impl Display for UnknownKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnknownKeys::Ignore => write!(f, "ignore"),
+            UnknownKeys::Warn => write!(f, "warn"),
+            UnknownKeys::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A dotted TOML key path, like `nix.extra_args."nix build"`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct KeyPath(Vec<String>);
+
+impl KeyPath {
+    /// Get a path to a key in the table at this path.
+    pub fn join(&self, key: &str) -> Self {
+        let mut segments = self.0.clone();
+        segments.push(key.to_owned());
+        Self(segments)
+    }
+
+    fn from_ignored(path: &serde_ignored::Path) -> Self {
+        fn push(path: &serde_ignored::Path, segments: &mut Vec<String>) {
+            match path {
+                serde_ignored::Path::Root => {}
+                serde_ignored::Path::Seq { parent, index } => {
+                    push(parent, segments);
+                    segments.push(index.to_string());
+                }
+                serde_ignored::Path::Map { parent, key } => {
+                    push(parent, segments);
+                    segments.push(key.clone());
+                }
+                serde_ignored::Path::Some { parent }
+                | serde_ignored::Path::NewtypeStruct { parent }
+                | serde_ignored::Path::NewtypeVariant { parent } => push(parent, segments),
+            }
+        }
+
+        let mut segments = Vec::new();
+        push(path, &mut segments);
+        Self(segments)
+    }
+
+    /// Get a path to a key nested under this one.
+    pub fn concat(&self, other: &KeyPath) -> Self {
+        let mut segments = self.0.clone();
+        segments.extend(other.0.iter().cloned());
+        Self(segments)
+    }
+
+    pub fn from_segments(segments: &[&str]) -> Self {
+        Self(
+            segments
+                .iter()
+                .map(|segment| (*segment).to_owned())
+                .collect(),
+        )
+    }
+
+    /// Find the span of the last key in this path in a parsed TOML document.
+    fn span(&self, root: &DeTable<'_>) -> Option<SourceSpan> {
+        let (last, parents) = self.0.split_last()?;
+        let mut table = root;
+        for segment in parents {
+            match table.get(segment.as_str())?.get_ref() {
+                DeValue::Table(inner) => table = inner,
+                _ => return None,
+            }
+        }
+        let (key, _) = table.get_key_value(last.as_str())?;
+        Some(key.span().into())
+    }
+
+    /// The most similar key `npingler` understands, if any are close enough.
+    fn suggestion(&self) -> Option<KeyPath> {
+        // Keys in `[host.<hostname>]` sections are the same as top-level keys.
+        if let [host, hostname, rest @ ..] = self.0.as_slice()
+            && host == "host"
+            && !rest.is_empty()
+        {
+            let prefix = KeyPath(vec![host.clone(), hostname.clone()]);
+            return KeyPath(rest.to_vec())
+                .suggestion()
+                .map(|suggestion| prefix.concat(&suggestion));
+        }
+
+        let key = self.to_string();
+        // Compare against known keys truncated to the same depth, so that a misspelled table
+        // like `[channel]` suggests `channels` rather than one of the keys in it.
+        KNOWN_KEYS
+            .iter()
+            .filter(|known| known.len() >= self.0.len())
+            .map(|known| KeyPath::from_segments(&known[..self.0.len()]))
+            .map(|known| (strsim::jaro(&key, &known.to_string()), known))
+            .filter(|(similarity, _)| *similarity > 0.8)
+            .max_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, known)| known)
+    }
+}
+
+impl Display for KeyPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            let bare = !segment.is_empty()
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if bare {
+                write!(f, "{segment}")?;
+            } else {
+                write!(f, "{segment:?}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("Failed to parse configuration file {path}")]
+pub struct ConfigParseError {
+    path: Utf8PathBuf,
+    #[source_code]
+    source_code: NamedSource<String>,
+    #[label("{message}")]
+    span: Option<SourceSpan>,
+    message: String,
+    #[help]
+    help: Option<String>,
+}
+
+impl ConfigParseError {
+    fn new(path: &Utf8Path, contents: &str, error: toml::de::Error) -> Self {
+        let span = error.span().map(SourceSpan::from);
+        let message = error.message().to_owned();
+        Self {
+            path: path.to_owned(),
+            source_code: NamedSource::new(path, contents.to_owned()),
+            // Without a span, there's nowhere to put the label.
+            help: span.is_none().then(|| message.clone()),
+            span,
+            message,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("Unknown configuration key `{key}` in {path}")]
+pub struct UnknownKey {
+    key: KeyPath,
+    path: Utf8PathBuf,
+    #[source_code]
+    source_code: NamedSource<String>,
+    #[label("unknown key")]
+    span: Option<SourceSpan>,
+    #[help]
+    help: Option<String>,
+    suggestion: Option<KeyPath>,
+}
+
+impl UnknownKey {
+    /// A short description of the key and a suggested correction, if any.
+    pub fn summary(&self) -> String {
+        match &self.suggestion {
+            Some(suggestion) => format!("{} (did you mean `{suggestion}`?)", self.key),
+            None => self.key.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("Configuration contains unknown keys")]
+#[diagnostic(help("Set `unknown_keys = \"warn\"` to allow unknown keys"))]
+pub struct UnknownKeysError {
+    #[related]
+    keys: Vec<UnknownKey>,
+}
+
+impl UnknownKeysError {
+    pub fn new(keys: Vec<UnknownKey>) -> Self {
+        Self { keys }
+    }
+}
+
+/// Deserialize a configuration file, collecting any keys that were not used.
+pub fn parse<T>(path: &Utf8Path, contents: &str) -> miette::Result<(T, Vec<UnknownKey>)>
+where
+    T: DeserializeOwned,
+{
+    let root =
+        DeTable::parse(contents).map_err(|error| ConfigParseError::new(path, contents, error))?;
+    let deserializer = toml::Deserializer::from(root.clone());
+
+    let mut unknown = Vec::new();
+    let value = serde_ignored::deserialize(deserializer, |key| {
+        unknown.push(KeyPath::from_ignored(&key));
+    })
+    .map_err(|error| ConfigParseError::new(path, contents, error))?;
+
+    let source_code = NamedSource::new(path, contents.to_owned());
+    let unknown = unknown
+        .into_iter()
+        .map(|key| {
+            let suggestion = key.suggestion();
+            UnknownKey {
+                span: key.span(root.get_ref()),
+                help: suggestion
+                    .as_ref()
+                    .map(|suggestion| format!("Did you mean `{suggestion}`?")),
+                suggestion,
+                key,
+                path: path.to_owned(),
+                source_code: source_code.clone(),
+            }
+        })
+        .collect();
+
+    Ok((value, unknown))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn suggestion(segments: &[&str]) -> Option<String> {
+        KeyPath::from_segments(segments)
+            .suggestion()
+            .map(|suggestion| suggestion.to_string())
+    }
+
+    #[test]
+    fn test_suggestion() {
+        assert_eq!(
+            suggestion(&["profle", "system"]).as_deref(),
+            Some("profile.system")
+        );
+        assert_eq!(
+            suggestion(&["nix", "ofline"]).as_deref(),
+            Some("nix.offline")
+        );
+        assert_eq!(suggestion(&["zzz"]), None);
+    }
+
+    #[test]
+    fn test_suggestion_for_table() {
+        // A misspelled table suggests the table, not a key in it.
+        assert_eq!(suggestion(&["channel"]).as_deref(), Some("channels"));
+    }
+
+    #[test]
+    fn test_suggestion_in_host_section() {
+        assert_eq!(
+            suggestion(&["host", "grandiflora", "registy", "pin_root"]).as_deref(),
+            Some("host.grandiflora.registry.pin_root")
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            KeyPath::from_segments(&["host", "my.host", "nix", "offline"]).to_string(),
+            "host.\"my.host\".nix.offline"
+        );
+    }
+}
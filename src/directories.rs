@@ -7,6 +7,7 @@ use tap::Tap;
 use xdg::BaseDirectories;
 
 use crate::nix::Nix;
+use crate::privilege::is_root;
 
 pub struct ProjectPaths {
     /// The user's home directory.
@@ -82,58 +83,157 @@ impl ProjectPaths {
         self.find_config_paths("default.nix")
     }
 
+    /// Get the directory to save build logs in, `~/.local/state/npingler/logs`, creating it if
+    /// needed.
+    pub fn log_dir(&self) -> miette::Result<Utf8PathBuf> {
+        let path = self
+            .project_xdg
+            .create_state_directory("logs")
+            .into_diagnostic()
+            .wrap_err("Failed to create log directory")?;
+        Utf8PathBuf::try_from(path).into_diagnostic()
+    }
+
+    /// Get the user's Nix Flake registry, `~/.config/nix/registry.json`.
+    pub fn user_registry_path(&self) -> miette::Result<Option<Utf8PathBuf>> {
+        match self.xdg.get_config_home() {
+            Some(config_home) => {
+                let mut path = Utf8PathBuf::try_from(config_home).into_diagnostic()?;
+                path.push("nix/registry.json");
+                Ok(Some(path))
+            }
+            None => Ok(None),
+        }
+    }
+
     pub fn home_dir(&self) -> &Utf8Path {
         &self.home_dir
     }
 
-    /// Get the path to the user's Nix profile, if it exists.
+    /// Get the path to the user's Nix profile.
+    ///
+    /// If the profile link doesn't exist yet, this is the profile it would point to once
+    /// [`Self::create_nix_profile_links`] creates it.
     pub fn nix_profile(&self, nix: &Nix) -> miette::Result<Utf8PathBuf> {
-        // I'm _pretty_ sure this does the same thing as upstream (minus the root profile
-        // handling).
-        //
-        // See: https://git.lix.systems/lix-project/lix/src/commit/5dc847b47b4e0e970d6a1cf2da0abd7a4e1bad2e/lix/libstore/profiles.cc#L331-L349
+        let profile_link = self.nix_profile_link(nix)?;
 
-        let profile_link = if nix.use_xdg_base_directories()?
-            && let Some(profile) = self.xdg_nix_profile()?
-        {
-            profile
-        } else {
-            let home_dir = self.home_dir().to_path_buf();
-            home_dir.tap_mut(|p| p.push(".nix-profile"))
-        };
+        if profile_link.symlink_metadata().is_ok() {
+            return crate::fs::resolve_symlink_once_utf8(profile_link);
+        }
 
-        match self.nix_profile_link_inner(&profile_link) {
-            Ok(()) => crate::fs::resolve_symlink_once_utf8(profile_link),
+        match self.nix_profiles_dir() {
+            Ok(Some(dir)) => Ok(dir.join("profile")),
+            Ok(None) => Ok(profile_link),
             Err(err) => {
-                tracing::debug!("Failed to get Nix profile link:\n{err}");
+                tracing::debug!("Failed to get Nix profiles directory:\n{err}");
                 Ok(profile_link)
             }
         }
     }
 
-    fn nix_profile_link_inner(&self, profile_link: &Utf8Path) -> miette::Result<()> {
-        if let Some(profile) = self.nix_profiles_dir()?.map(|mut dir| {
+    /// Create the user's Nix profile link, like Nix does, if it doesn't exist.
+    ///
+    /// When running as `root`, also create `/nix/var/nix/profiles/default`, which is what most
+    /// installers and init scripts expect.
+    pub fn create_nix_profile_links(&self, nix: &Nix) -> miette::Result<()> {
+        let profile_link = self.nix_profile_link(nix)?;
+        let Some(profile) = self.nix_profiles_dir()?.map(|mut dir| {
             dir.push("profile");
             dir
-        }) && profile_link.symlink_metadata().is_err()
-        {
-            fs_err::os::unix::fs::symlink(&profile, profile_link).into_diagnostic()?;
+        }) else {
+            return Ok(());
+        };
+
+        if profile_link.symlink_metadata().is_err() {
+            tracing::debug!("Linking {profile_link} to {profile}");
+            fs_err::os::unix::fs::symlink(&profile, &profile_link).into_diagnostic()?;
+        }
+
+        let default_profile = Self::system_nix_profile();
+        if is_root() && default_profile.symlink_metadata().is_err() {
+            tracing::debug!("Linking {default_profile} to {profile}");
+            fs_err::os::unix::fs::symlink(&profile, &default_profile).into_diagnostic()?;
         }
 
         Ok(())
     }
 
-    /// Get `~/.local/state/nix/profiles`.
+    /// Get the user's Nix profile link, e.g. `~/.nix-profile` or `~/.local/state/nix/profile`.
+    pub fn nix_profile_link(&self, nix: &Nix) -> miette::Result<Utf8PathBuf> {
+        // I'm _pretty_ sure this does the same thing as upstream.
+        //
+        // See: https://git.lix.systems/lix-project/lix/src/commit/5dc847b47b4e0e970d6a1cf2da0abd7a4e1bad2e/lix/libstore/profiles.cc#L331-L349
+
+        if nix.use_xdg_base_directories()?
+            && let Some(profile) = self.xdg_nix_profile()?
+        {
+            Ok(profile)
+        } else {
+            Ok(self.legacy_nix_profile())
+        }
+    }
+
+    /// Get the non-XDG Nix profile link, `~/.nix-profile`.
+    pub fn legacy_nix_profile(&self) -> Utf8PathBuf {
+        self.home_dir()
+            .to_path_buf()
+            .tap_mut(|p| p.push(".nix-profile"))
+    }
+
+    /// Get the profile link for a named `npingler` profile, e.g.
+    /// `~/.local/state/nix/profiles/npingler-dev-tools`.
+    ///
+    /// If `system` is set, the link is in the system-wide profiles directory instead, e.g.
+    /// `/nix/var/nix/profiles/npingler-dev-tools`.
+    ///
+    /// The `npingler-` prefix keeps these from clashing with `profile` and `channels`, which
+    /// live in the same directory.
+    pub fn named_nix_profile(&self, name: &str, system: bool) -> miette::Result<Utf8PathBuf> {
+        let mut dir = if system {
+            Self::system_nix_profiles_dir()
+        } else {
+            self.nix_profiles_dir()?
+                .ok_or_else(|| miette!("Couldn't find the Nix profiles directory"))?
+        };
+        dir.push(format!("npingler-{name}"));
+        Ok(dir)
+    }
+
+    /// Get `~/.local/state/nix/profiles`, or `/nix/var/nix/profiles/per-user/root` when
+    /// running as `root`.
     fn nix_profiles_dir(&self) -> miette::Result<Option<Utf8PathBuf>> {
+        if is_root() {
+            return Ok(Some(Self::system_nix_profiles_dir().join("per-user/root")));
+        }
+
         Ok(self.xdg_nix_dir()?.map(|mut dir| {
             dir.push("profiles");
             dir
         }))
     }
 
+    /// Get the Nix state directory, `/nix/var/nix` unless overridden with `$NIX_STATE_DIR`.
+    fn nix_state_dir() -> Utf8PathBuf {
+        std::env::var("NIX_STATE_DIR")
+            .map(Utf8PathBuf::from)
+            .unwrap_or_else(|_| Utf8PathBuf::from("/nix/var/nix"))
+    }
+
+    /// Get `/nix/var/nix/profiles`.
+    fn system_nix_profiles_dir() -> Utf8PathBuf {
+        Self::nix_state_dir().join("profiles")
+    }
+
+    /// Get the default system-wide profile, `/nix/var/nix/profiles/default`.
+    ///
+    /// On multi-user installs, this profile is on every user's `$PATH`.
+    pub fn system_nix_profile() -> Utf8PathBuf {
+        Self::system_nix_profiles_dir().join("default")
+    }
+
     /// Get the new `use-xdg-base-directories` Nix profile path,
     /// `~/.local/state/nix/profile`.
-    fn xdg_nix_profile(&self) -> miette::Result<Option<Utf8PathBuf>> {
+    pub fn xdg_nix_profile(&self) -> miette::Result<Option<Utf8PathBuf>> {
         Ok(self.xdg_nix_dir()?.and_then(|mut dir| {
             dir.push("profile");
             if dir.symlink_metadata().is_ok() {
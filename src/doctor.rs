@@ -0,0 +1,425 @@
+use std::fmt::Display;
+use std::process::Command;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use miette::miette;
+use owo_colors::OwoColorize;
+
+use crate::config::Config;
+use crate::config::ConfigFile;
+use crate::format_bulleted_list;
+use crate::nix::Nix;
+
+/// How bad a problem is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// The result of checking one part of the environment.
+struct Check {
+    severity: Severity,
+    message: String,
+    /// A suggestion for fixing the problem.
+    fix: Option<String>,
+}
+
+impl Check {
+    fn ok(message: impl Display) -> Self {
+        Self {
+            severity: Severity::Ok,
+            message: message.to_string(),
+            fix: None,
+        }
+    }
+
+    fn warning(message: impl Display, fix: impl Display) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.to_string(),
+            fix: Some(fix.to_string()),
+        }
+    }
+
+    fn error(message: impl Display, fix: impl Display) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.to_string(),
+            fix: Some(fix.to_string()),
+        }
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Ok => write!(f, "{} {}", "✓".green(), self.message)?,
+            Severity::Warning => write!(f, "{} {}", "!".yellow(), self.message)?,
+            Severity::Error => write!(f, "{} {}", "✗".red(), self.message)?,
+        }
+        if let Some(fix) = &self.fix {
+            write!(f, "\n  {} {fix}", "fix:".cyan())?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the environment `npingler` runs in and explains any problems.
+pub struct Doctor<'c> {
+    config: &'c Config,
+    /// Why the configuration files failed to load, if they did.
+    load_error: Option<miette::Report>,
+    checks: Vec<Check>,
+}
+
+impl<'c> Doctor<'c> {
+    pub fn new(config: &'c Config, load_error: Option<miette::Report>) -> Self {
+        Self {
+            config,
+            load_error,
+            checks: Vec::new(),
+        }
+    }
+
+    pub fn run(mut self) -> miette::Result<()> {
+        self.check_config_load();
+        self.check_config_file();
+
+        match self.check_nix() {
+            Some(nix) => {
+                self.check_nix_command(&nix);
+                self.check_nix_profile(&nix);
+            }
+            None => {
+                tracing::debug!("Skipping Nix profile checks, `nix` is not available");
+            }
+        }
+
+        self.check_npins();
+        self.check_root_registry();
+        self.check_root_channels();
+
+        for check in &self.checks {
+            println!("{check}");
+        }
+
+        let errors = self
+            .checks
+            .iter()
+            .filter(|check| check.severity == Severity::Error)
+            .count();
+        if errors > 0 {
+            Err(miette!("{errors} checks failed"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_config_load(&mut self) {
+        if let Some(err) = self.load_error.take() {
+            self.checks.push(Check::error(
+                format!(
+                    "Failed to load the configuration, so the other checks use the default settings: {err}"
+                ),
+                "Fix the configuration files below; other `npingler` commands will fail until then",
+            ));
+        }
+    }
+
+    fn check_config_file(&mut self) {
+        if self.config.paths().is_empty() {
+            self.checks.push(Check::ok(
+                "No configuration file found, using default settings",
+            ));
+        }
+
+        for path in self.config.paths() {
+            match ConfigFile::unknown_keys(path) {
+                Ok(unknown) if unknown.is_empty() => {
+                    self.checks
+                        .push(Check::ok(format!("Configuration file {path} is valid")));
+                }
+                Ok(unknown) => {
+                    self.checks.push(Check::warning(
+                        format!(
+                            "Configuration file {path} contains unknown keys:\n{}",
+                            format_bulleted_list(unknown.iter().map(|key| key.summary()))
+                        ),
+                        "Remove the keys or fix their spelling; see `npingler config init -` for the available settings",
+                    ));
+                }
+                Err(err) => {
+                    self.checks.push(Check::error(
+                        format!("Failed to parse configuration file {path}: {err:?}"),
+                        "Fix the syntax errors in the configuration file",
+                    ));
+                }
+            }
+        }
+    }
+
+    fn check_nix(&mut self) -> Option<Nix> {
+        let nix = match self.config.nix() {
+            Ok(nix) => nix,
+            Err(err) => {
+                self.checks.push(Check::error(
+                    format!("Failed to find Nix: {err}"),
+                    "Install Nix and make sure `nix` and `nix-env` are on your `$PATH`",
+                ));
+                return None;
+            }
+        };
+
+        match nix.version() {
+            Ok(version) => self.checks.push(Check::ok(format!(
+                "Found {version} at {}",
+                nix.nix_program()
+            ))),
+            Err(err) => self.checks.push(Check::error(
+                format!("Failed to run {} --version: {err}", nix.nix_program()),
+                "Check your Nix installation",
+            )),
+        }
+
+        match nix.nix_env_version() {
+            Ok(version) => self.checks.push(Check::ok(format!(
+                "Found {version} at {}",
+                nix.nix_env_program()
+            ))),
+            Err(err) => self.checks.push(Check::error(
+                format!("Failed to run {} --version: {err}", nix.nix_env_program()),
+                "Check your Nix installation",
+            )),
+        }
+
+        Some(nix)
+    }
+
+    /// Check that Nix supports the `nix-command` experimental feature.
+    ///
+    /// `npingler` passes `--extra-experimental-features nix-command` to every `nix` command it
+    /// runs (see [`Nix::nix_command`]), so the feature doesn't need to be enabled in `nix.conf`,
+    /// but older versions of Nix don't have it at all.
+    fn check_nix_command(&mut self, nix: &Nix) {
+        match nix.get_config("experimental-features") {
+            Ok(_) => self.checks.push(Check::ok(
+                "The `nix-command` experimental feature is available",
+            )),
+            Err(err) => self.checks.push(Check::error(
+                format!("The `nix-command` experimental feature is not available: {err}"),
+                "Upgrade to a version of Nix which supports `nix-command` (2.4 or newer)",
+            )),
+        }
+    }
+
+    fn check_nix_profile(&mut self, nix: &Nix) {
+        let project_paths = self.config.project_paths();
+
+        match nix.use_xdg_base_directories() {
+            Ok(use_xdg) => {
+                let xdg_profile = project_paths.xdg_nix_profile().ok().flatten();
+                let legacy_profile = project_paths.legacy_nix_profile();
+                match (use_xdg, xdg_profile) {
+                    (true, Some(xdg_profile)) => self.checks.push(Check::ok(format!(
+                        "`use-xdg-base-directories` is enabled and the Nix profile is {xdg_profile}"
+                    ))),
+                    (true, None) => self.checks.push(Check::warning(
+                        format!(
+                            "`use-xdg-base-directories` is enabled but `~/.local/state/nix/profile` does not exist, so {legacy_profile} is used instead"
+                        ),
+                        format!("Move {legacy_profile} to `~/.local/state/nix/profile`"),
+                    )),
+                    (false, Some(xdg_profile)) => self.checks.push(Check::warning(
+                        format!(
+                            "{xdg_profile} exists but `use-xdg-base-directories` is disabled, so {legacy_profile} is used instead"
+                        ),
+                        "Set `use-xdg-base-directories = true` in `nix.conf`, or remove the unused profile link",
+                    )),
+                    (false, None) => self.checks.push(Check::ok(format!(
+                        "`use-xdg-base-directories` is disabled and the Nix profile is {legacy_profile}"
+                    ))),
+                }
+            }
+            Err(err) => self.checks.push(Check::warning(
+                format!("Failed to read the `use-xdg-base-directories` Nix setting: {err}"),
+                "Check that `nix config show` works",
+            )),
+        }
+
+        match self.config.nix_profile(nix) {
+            Ok(profile) => self.check_profile_chain(&profile),
+            Err(err) => self.checks.push(Check::error(
+                format!("Failed to resolve the Nix profile: {err}"),
+                "Set `profile.file` in your configuration or pass `--profile`",
+            )),
+        }
+    }
+
+    fn check_profile_chain(&mut self, profile: &Utf8Path) {
+        // Symlink loops would otherwise run forever.
+        const MAX_LINKS: usize = 40;
+
+        let mut chain = vec![profile.to_owned()];
+        let mut path = profile.to_owned();
+        loop {
+            match fs_err::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_symlink() => {
+                    if chain.len() > MAX_LINKS {
+                        self.checks.push(Check::error(
+                            format!(
+                                "Nix profile {profile} has too many symlinks:\n{}",
+                                format_bulleted_list(&chain)
+                            ),
+                            "Remove the symlink loop and run `npingler switch`",
+                        ));
+                        return;
+                    }
+                    match crate::fs::resolve_symlink_once_utf8(path.clone()) {
+                        Ok(dest) => {
+                            path = dest;
+                            chain.push(path.clone());
+                        }
+                        Err(err) => {
+                            self.checks.push(Check::error(
+                                format!("Failed to read Nix profile symlink {path}: {err}"),
+                                "Remove the broken symlink and run `npingler switch`",
+                            ));
+                            return;
+                        }
+                    }
+                }
+                Ok(_) => break,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    if chain.len() == 1 {
+                        self.checks.push(Check::warning(
+                            format!("Nix profile {profile} does not exist"),
+                            "Run `npingler switch` to create it",
+                        ));
+                    } else {
+                        self.checks.push(Check::error(
+                            format!(
+                                "Nix profile {profile} is a broken symlink:\n{}",
+                                format_bulleted_list(&chain)
+                            ),
+                            "Remove the broken symlink and run `npingler switch`",
+                        ));
+                    }
+                    return;
+                }
+                Err(err) => {
+                    self.checks.push(Check::error(
+                        format!("Failed to read Nix profile {path}: {err}"),
+                        "Check the permissions of your Nix profile",
+                    ));
+                    return;
+                }
+            }
+        }
+
+        if is_store_path(&path) {
+            self.checks
+                .push(Check::ok(format!("Nix profile {profile} points to {path}")));
+        } else {
+            self.checks.push(Check::warning(
+                format!(
+                    "Nix profile {profile} does not point to the Nix store:\n{}",
+                    format_bulleted_list(&chain)
+                ),
+                "Move the profile out of the way and run `npingler switch`",
+            ));
+        }
+    }
+
+    fn check_npins(&mut self) {
+        let npins = match crate::which::which_global("npins") {
+            Ok(npins) => npins,
+            Err(err) => {
+                self.checks.push(Check::warning(
+                    format!("Failed to find `npins`: {err}"),
+                    "Install `npins` to use `npingler update`",
+                ));
+                return;
+            }
+        };
+
+        match crate::nix::program_version(&mut Command::new(&npins)) {
+            Ok(version) => self
+                .checks
+                .push(Check::ok(format!("Found {version} at {npins}"))),
+            Err(err) => self.checks.push(Check::warning(
+                format!("Failed to run {npins} --version: {err}"),
+                "Check your `npins` installation",
+            )),
+        }
+    }
+
+    fn check_root_registry(&mut self) {
+        let path = match self.config.root_registry_path() {
+            Ok(path) => path,
+            Err(err) => {
+                self.checks.push(Check::error(
+                    format!("Failed to resolve the `root` Nix registry path: {err}"),
+                    "Fix the `registry.root_path` setting",
+                ));
+                return;
+            }
+        };
+
+        match Nix::parse_registry(&path) {
+            Ok(Some(_)) => self
+                .checks
+                .push(Check::ok(format!("`root` Nix registry {path} is valid"))),
+            Ok(None) if self.config.registry_pin_root() => self.checks.push(Check::ok(format!(
+                "`root` Nix registry {path} does not exist yet and will be created"
+            ))),
+            Ok(None) => self.checks.push(Check::ok(format!(
+                "`root` Nix registry {path} does not exist"
+            ))),
+            Err(err) => self.checks.push(Check::error(
+                format!("Failed to parse `root` Nix registry {path}: {err:?}"),
+                format!("Fix or remove {path}"),
+            )),
+        }
+    }
+
+    fn check_root_channels(&mut self) {
+        let profile = match self.config.channels_root_profile() {
+            Ok(profile) => profile,
+            Err(err) => {
+                self.checks.push(Check::error(
+                    format!("Failed to resolve the `root` channels profile: {err}"),
+                    "Fix the `channels.root_profile` setting",
+                ));
+                return;
+            }
+        };
+
+        let exists = fs_err::symlink_metadata(&profile).is_ok();
+        match (exists, self.config.channels_pin_root()) {
+            (true, _) => self.checks.push(Check::ok(format!(
+                "`root` channels profile {profile} exists"
+            ))),
+            (false, true) => self.checks.push(Check::warning(
+                format!("`root` channels profile {profile} does not exist"),
+                format!(
+                    "Check that {} exists and that `channels.root_profile` is correct",
+                    parent_or_self(&profile)
+                ),
+            )),
+            (false, false) => self.checks.push(Check::ok(format!(
+                "`root` channels profile {profile} does not exist, but channel pinning is disabled"
+            ))),
+        }
+    }
+}
+
+fn parent_or_self(path: &Utf8Path) -> Utf8PathBuf {
+    path.parent().unwrap_or(path).to_owned()
+}
+
+fn is_store_path(path: &Utf8Path) -> bool {
+    path.starts_with(crate::nix::store_dir())
+}
@@ -0,0 +1,322 @@
+use std::sync::LazyLock;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use miette::NamedSource;
+use miette::SourceOffset;
+use miette::SourceSpan;
+use regex::Regex;
+
+/// Matches a position in Nix's error output, like `at /path/to/default.nix:12:5:`.
+static POSITION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^at (/.+):(\d+):(\d+):?$").expect("Regex is valid"));
+
+/// Matches a line of a source snippet in Nix's error output, like `12|   foo = bar;`.
+static SNIPPET: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\d*\s*\|").expect("Regex is valid"));
+
+/// A position in a Nix file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Position {
+    path: Utf8PathBuf,
+    line: usize,
+    column: usize,
+}
+
+impl Position {
+    fn parse(line: &str) -> Option<Self> {
+        let captures = POSITION.captures(line)?;
+        Some(Self {
+            path: Utf8PathBuf::from(&captures[1]),
+            line: captures[2].parse().ok()?,
+            column: captures[3].parse().ok()?,
+        })
+    }
+
+    /// Read the file and find the span of the position, if the file is in `directory`.
+    fn source(&self, directory: &Utf8Path) -> Option<(NamedSource<String>, SourceSpan)> {
+        if !self.path.starts_with(directory) {
+            return None;
+        }
+        let contents = fs_err::read_to_string(&self.path)
+            .inspect_err(|err| tracing::debug!("Failed to read {}: {err}", self.path))
+            .ok()?;
+        let offset = SourceOffset::from_location(&contents, self.line, self.column);
+        // Underline the identifier or token at the position, if any.
+        let length = contents[offset.offset()..]
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '\'' | '.'))
+            .map(char::len_utf8)
+            .sum::<usize>()
+            .max(1);
+        Some((
+            NamedSource::new(&self.path, contents).with_language("Nix"),
+            SourceSpan::new(offset, length),
+        ))
+    }
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.path, self.line, self.column)
+    }
+}
+
+/// A frame in Nix's evaluation trace, like `… while evaluating attribute 'packages'`.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{description}")]
+pub struct TraceFrame {
+    description: String,
+    #[source_code]
+    source_code: Option<NamedSource<String>>,
+    #[label]
+    span: Option<SourceSpan>,
+    #[help]
+    help: Option<String>,
+}
+
+/// A Nix evaluation error, with a source snippet from the user's configuration.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("{message}")]
+pub struct EvalFailure {
+    message: String,
+    #[source_code]
+    source_code: Option<NamedSource<String>>,
+    #[label("{label}")]
+    span: Option<SourceSpan>,
+    label: String,
+    #[help]
+    help: Option<String>,
+    #[related]
+    trace: Vec<TraceFrame>,
+}
+
+impl EvalFailure {
+    /// Parse Nix's error output.
+    ///
+    /// Source snippets are only shown for files in `directory`, which contains the user's
+    /// configuration. The evaluation trace is only shown if `show_trace` is set.
+    ///
+    /// Returns `None` if the output doesn't contain an error message.
+    pub fn parse(stderr: &str, directory: &Utf8Path, show_trace: bool) -> Option<Self> {
+        let mut frames: Vec<(String, Option<Position>)> = Vec::new();
+        let mut message: Vec<&str> = Vec::new();
+        let mut position = None;
+        let mut in_message = false;
+
+        for line in stderr.lines() {
+            let line = line.trim();
+            if line.is_empty() || SNIPPET.is_match(line) {
+                continue;
+            }
+
+            if let Some(description) = line.strip_prefix('…') {
+                frames.push((description.trim().to_owned(), None));
+                in_message = false;
+            } else if let Some(found) = Position::parse(line) {
+                if in_message {
+                    position.get_or_insert(found);
+                } else if let Some((_, frame_position)) = frames.last_mut() {
+                    frame_position.get_or_insert(found);
+                }
+            } else if let Some(rest) = line.strip_prefix("error:") {
+                // The first line may be a bare `error:` followed by the trace.
+                if !rest.trim().is_empty() {
+                    message = vec![rest.trim()];
+                    position = None;
+                    in_message = true;
+                }
+            } else if in_message && position.is_none() {
+                message.push(line);
+            }
+        }
+
+        if message.is_empty() {
+            return None;
+        }
+
+        // Point at the error itself if it's in the user's configuration, or otherwise the
+        // innermost part of the trace that is.
+        let (source, label) = position
+            .as_ref()
+            .and_then(|position| position.source(directory))
+            .map(|source| (Some(source), "here".to_owned()))
+            .or_else(|| {
+                frames.iter().rev().find_map(|(description, position)| {
+                    position
+                        .as_ref()?
+                        .source(directory)
+                        .map(|source| (Some(source), description.clone()))
+                })
+            })
+            .unwrap_or((None, String::new()));
+        let (source_code, span) = source.unzip();
+
+        let mut help = position
+            .as_ref()
+            .filter(|_| span.is_none())
+            .map(|position| format!("At {position}"));
+        let trace = if show_trace {
+            frames
+                .into_iter()
+                .map(|(description, position)| {
+                    let source = position
+                        .as_ref()
+                        .and_then(|position| position.source(directory));
+                    let (source_code, span) = source.unzip();
+                    TraceFrame {
+                        description,
+                        help: position
+                            .filter(|_| span.is_none())
+                            .map(|position| format!("At {position}")),
+                        source_code,
+                        span,
+                    }
+                })
+                .collect()
+        } else {
+            if !frames.is_empty() {
+                let hidden = format!(
+                    "{} evaluation trace frames hidden; use `--verbose` to show them",
+                    frames.len()
+                );
+                help = Some(match help {
+                    Some(help) => format!("{help}\n{hidden}"),
+                    None => hidden,
+                });
+            }
+            Vec::new()
+        };
+
+        Some(Self {
+            message: message.join("\n"),
+            source_code,
+            span,
+            label,
+            help,
+            trace,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a configuration to a temporary directory and get Nix's error output for it, with
+    /// `@dir@` replaced with the directory.
+    fn setup(stderr: &str) -> (tempfile::TempDir, Utf8PathBuf, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Utf8PathBuf::from_path_buf(dir.path().to_owned()).unwrap();
+        fs_err::write(
+            path.join("default.nix"),
+            "{\n  npingler.grandiflora = {\n    packages = foo;\n  };\n}\n",
+        )
+        .unwrap();
+        let stderr = stderr.replace("@dir@", path.as_str());
+        (dir, path, stderr)
+    }
+
+    /// The text a span points at in the configuration.
+    fn spanned(path: &Utf8Path, span: Option<SourceSpan>) -> String {
+        let span = span.expect("Span is set");
+        let contents = fs_err::read_to_string(path.join("default.nix")).unwrap();
+        contents[span.offset()..span.offset() + span.len()].to_owned()
+    }
+
+    /// `nix eval` output for an undefined variable, from Nix 2.24.
+    const UNDEFINED_VARIABLE: &str = "\
+error:
+       … while evaluating attribute 'packages'
+         at @dir@/default.nix:3:5:
+            2|   npingler.grandiflora = {
+            3|     packages = foo;
+             |     ^
+            4|   };
+
+       error: undefined variable 'foo'
+       at @dir@/default.nix:3:16:
+            2|   npingler.grandiflora = {
+            3|     packages = foo;
+             |                ^
+            4|   };
+";
+
+    #[test]
+    fn test_parse_undefined_variable() {
+        let (_dir, path, stderr) = setup(UNDEFINED_VARIABLE);
+        let failure = EvalFailure::parse(&stderr, &path, false).unwrap();
+        assert_eq!(failure.message, "undefined variable 'foo'");
+        assert_eq!(failure.label, "here");
+        assert_eq!(spanned(&path, failure.span), "foo");
+        assert_eq!(
+            failure.help.as_deref(),
+            Some("1 evaluation trace frames hidden; use `--verbose` to show them")
+        );
+        assert!(failure.trace.is_empty());
+    }
+
+    #[test]
+    fn test_parse_with_trace() {
+        let (_dir, path, stderr) = setup(UNDEFINED_VARIABLE);
+        let failure = EvalFailure::parse(&stderr, &path, true).unwrap();
+        assert_eq!(failure.help, None);
+        assert_eq!(failure.trace.len(), 1);
+        assert_eq!(
+            failure.trace[0].description,
+            "while evaluating attribute 'packages'"
+        );
+        assert_eq!(spanned(&path, failure.trace[0].span), "packages");
+    }
+
+    #[test]
+    fn test_parse_error_outside_configuration() {
+        let (_dir, path, stderr) = setup(
+            "\
+error:
+       … while calling the 'throw' builtin
+         at /nix/store/abc-source/lib/trivial.nix:12:3:
+           11|
+           12|   throw msg;
+             |   ^
+
+       … from call site
+         at @dir@/default.nix:3:16:
+            2|   npingler.grandiflora = {
+            3|     packages = foo;
+             |                ^
+
+       error: the package is broken
+       spanning two lines
+",
+        );
+        let failure = EvalFailure::parse(&stderr, &path, false).unwrap();
+        assert_eq!(failure.message, "the package is broken\nspanning two lines");
+        // The innermost frame in the user's configuration is shown instead.
+        assert_eq!(failure.label, "from call site");
+        assert_eq!(spanned(&path, failure.span), "foo");
+    }
+
+    #[test]
+    fn test_parse_position_outside_configuration() {
+        let (_dir, path, _) = setup("");
+        let failure = EvalFailure::parse(
+            "error: attribute 'nope' missing\n       at /nix/store/abc-source/default.nix:1:2:\n",
+            &path,
+            false,
+        )
+        .unwrap();
+        assert_eq!(failure.message, "attribute 'nope' missing");
+        assert_eq!(failure.span, None);
+        assert_eq!(
+            failure.help.as_deref(),
+            Some("At /nix/store/abc-source/default.nix:1:2")
+        );
+    }
+
+    #[test]
+    fn test_parse_no_error() {
+        let (_dir, path, _) = setup("");
+        assert!(EvalFailure::parse("warning: Git tree is dirty\n", &path, false).is_none());
+    }
+}
@@ -0,0 +1,24 @@
+use camino::Utf8Path;
+use owo_colors::OwoColorize;
+
+/// Format a colored unified diff of the changes to a file.
+pub fn format_diff(path: &Utf8Path, old: &str, new: &str) -> String {
+    let diff = similar::TextDiff::from_lines(old, new);
+    let mut displayed = String::new();
+    for line in diff
+        .unified_diff()
+        .header(path.as_str(), path.as_str())
+        .to_string()
+        .lines()
+    {
+        if line.starts_with('+') && !line.starts_with("+++") {
+            displayed.push_str(&line.green().to_string());
+        } else if line.starts_with('-') && !line.starts_with("---") {
+            displayed.push_str(&line.red().to_string());
+        } else {
+            displayed.push_str(line);
+        }
+        displayed.push('\n');
+    }
+    displayed
+}
@@ -1,6 +1,8 @@
 use std::path::Path;
 
+use camino::Utf8Path;
 use camino::Utf8PathBuf;
+use miette::Context;
 use miette::IntoDiagnostic;
 use miette::miette;
 
@@ -39,3 +41,29 @@ pub fn resolve_symlink_once_utf8(path: Utf8PathBuf) -> miette::Result<Utf8PathBu
             .join(&Utf8PathBuf::try_from(dest).into_diagnostic()?))
     }
 }
+
+/// Recursively copy a directory, preserving symlinks and skipping `.git` directories.
+pub fn copy_dir_all(from: &Utf8Path, to: &Utf8Path) -> miette::Result<()> {
+    let walker = walkdir::WalkDir::new(from)
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != ".git");
+    for entry in walker {
+        let entry = entry.into_diagnostic()?;
+        let path = Utf8Path::from_path(entry.path())
+            .ok_or_else(|| miette!("Path is not UTF-8: {:?}", entry.path()))?;
+        let relative = path.strip_prefix(from).into_diagnostic()?;
+        let dest = to.join(relative);
+        let file_type = entry.file_type();
+        if file_type.is_symlink() {
+            let target = fs_err::read_link(path).into_diagnostic()?;
+            std::os::unix::fs::symlink(&target, &dest)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to create symlink {dest}"))?;
+        } else if file_type.is_dir() {
+            fs_err::create_dir_all(&dest).into_diagnostic()?;
+        } else {
+            fs_err::copy(path, &dest).into_diagnostic()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use camino::Utf8PathBuf;
+use itertools::Itertools;
+
+use crate::config::HostnameSource;
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("No configuration for host `{host}` in {nix_file}")]
+#[diagnostic(help("{help}"))]
+pub struct HostNotFound {
+    host: String,
+    nix_file: Utf8PathBuf,
+    help: String,
+}
+
+impl HostNotFound {
+    pub fn new(
+        hostname: &str,
+        host: &str,
+        source: HostnameSource,
+        nix_file: Utf8PathBuf,
+        hosts: &[String],
+    ) -> Self {
+        let mut help = format!("The hostname `{hostname}` was determined by {source}");
+        if host != hostname {
+            help.push_str(&format!(" and mapped to `{host}` by the `hosts` setting"));
+        }
+        help.push_str(", and there is no `npingler.default` attribute to fall back to.");
+
+        let similar = similar_hosts(host, hosts);
+        if !similar.is_empty() {
+            help.push_str(&format!(
+                " Did you mean {}?",
+                similar.iter().map(|host| format!("`{host}`")).join(" or ")
+            ));
+        }
+
+        if hosts.is_empty() {
+            help.push_str(" No hosts are defined in the `npingler` attribute set.");
+        } else {
+            help.push_str(" Use `--hostname` to choose a host; `npingler hosts` lists them.");
+        }
+
+        Self {
+            host: host.to_owned(),
+            nix_file,
+            help,
+        }
+    }
+}
+
+/// Get the hosts most similar to `hostname`, most similar first.
+pub fn similar_hosts<'h>(hostname: &str, hosts: &'h [String]) -> Vec<&'h str> {
+    hosts
+        .iter()
+        .map(|host| (strsim::jaro_winkler(hostname, host), host.as_str()))
+        .filter(|(similarity, _)| *similarity > 0.7)
+        .sorted_by(|(a, _), (b, _)| b.total_cmp(a))
+        .map(|(_, host)| host)
+        .take(3)
+        .collect()
+}
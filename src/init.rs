@@ -0,0 +1,240 @@
+use std::process::Command;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use command_error::CommandExt;
+use command_error::Utf8ProgramAndArgs;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::miette;
+
+use crate::config::Config;
+use crate::config::DEFAULT_CONFIG;
+use crate::config::RunMode;
+use crate::packages_file::PackagesFile;
+
+/// The template for `default.nix`.
+const TEMPLATE: &str = include_str!("../template.nix");
+
+/// Packages to list in `packages.toml` when not importing the current profile.
+const DEFAULT_PACKAGES: &[&str] = &["npins"];
+
+/// Words which can't be used as bare attribute names in Nix.
+const NIX_KEYWORDS: &[&str] = &[
+    "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
+/// Scaffolds a new configuration for `npingler init`.
+pub struct Init<'c> {
+    config: &'c Config,
+    directory: Utf8PathBuf,
+    /// The `npingler.<host>` attribute to create.
+    host: String,
+    import_profile: bool,
+    nixpkgs_channel: &'c str,
+    npingler_repo: &'c str,
+}
+
+impl<'c> Init<'c> {
+    pub fn new(
+        config: &'c Config,
+        directory: Option<&Utf8Path>,
+        import_profile: bool,
+        nixpkgs_channel: &'c str,
+        npingler_repo: &'c str,
+    ) -> miette::Result<Self> {
+        let directory = match directory {
+            Some(directory) => directory.to_owned(),
+            None => Self::default_directory(config)?,
+        };
+        let hostname = config.hostname()?;
+        let host = config
+            .host_alias(&hostname)?
+            .unwrap_or(&hostname)
+            .to_owned();
+        Ok(Self {
+            config,
+            directory,
+            host,
+            import_profile,
+            nixpkgs_channel,
+            npingler_repo,
+        })
+    }
+
+    fn default_directory(config: &Config) -> miette::Result<Utf8PathBuf> {
+        let config_path = config.project_paths().default_config_path()?;
+        config_path
+            .parent()
+            .map(Utf8Path::to_path_buf)
+            .ok_or_else(|| miette!("Configuration file has no parent directory: {config_path}"))
+    }
+
+    /// Create the configuration and return the path of the new `default.nix`.
+    pub fn run(&self) -> miette::Result<Utf8PathBuf> {
+        let nix_file = self.directory.join("default.nix");
+        if nix_file.exists() {
+            return Err(miette!(
+                help = "Use `npingler build` to build the existing configuration",
+                "Configuration already exists: {nix_file}"
+            ));
+        }
+
+        // Get the packages first, so that we don't leave a half-initialized directory behind if
+        // this fails.
+        let packages = self.packages()?;
+        let npins = crate::which::which_global("npins")
+            .wrap_err("`npins` is needed to pin `nixpkgs` and `npingler`")?;
+
+        match self.config.run_mode() {
+            RunMode::Dry => {
+                tracing::info!("Would create {}", self.directory);
+            }
+            RunMode::Wet => {
+                fs_err::create_dir_all(&self.directory).into_diagnostic()?;
+            }
+        }
+
+        self.init_npins(&npins)?;
+        self.write_file(&nix_file, &self.nix_file_contents())?;
+
+        let mut packages_file = PackagesFile::read(&self.directory)?;
+        for package in &packages {
+            packages_file.add(None, package)?;
+        }
+        packages_file.write(&self.config.run_mode())?;
+
+        let default_directory = Self::default_directory(self.config)?;
+        if self.directory == default_directory {
+            let config_path = self.directory.join("config.toml");
+            if self.config.paths().is_empty() && !config_path.exists() {
+                self.write_file(&config_path, DEFAULT_CONFIG)?;
+            }
+        } else {
+            tracing::info!(
+                "{} isn't the default configuration directory; use `--file {}` or set `file` in \
+                `config.toml` to use it",
+                self.directory,
+                self.directory
+            );
+        }
+
+        Ok(nix_file)
+    }
+
+    fn write_file(&self, path: &Utf8Path, contents: &str) -> miette::Result<()> {
+        match self.config.run_mode() {
+            RunMode::Dry => {
+                tracing::info!("Would write {path}:\n{contents}");
+            }
+            RunMode::Wet => {
+                tracing::info!("Writing {path}");
+                fs_err::write(path, contents).into_diagnostic()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn run_command(&self, command: &mut Command) -> miette::Result<()> {
+        match self.config.run_mode() {
+            RunMode::Dry => {
+                tracing::info!("Would run: {}", Utf8ProgramAndArgs::from(&*command));
+            }
+            RunMode::Wet => {
+                command.status_checked().into_diagnostic()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Initialize `npins` and add the `nixpkgs` and `npingler` pins, if they're missing.
+    fn init_npins(&self, npins: &Utf8Path) -> miette::Result<()> {
+        let npins_command = || {
+            let mut command = Command::new(npins);
+            command.current_dir(&self.directory);
+            command
+        };
+
+        let sources = self.directory.join("npins").join("sources.json");
+        let existing = if sources.exists() {
+            crate::npins::read_sources(&sources)?.into_keys().collect()
+        } else {
+            self.run_command(npins_command().args(["init", "--bare"]))?;
+            Vec::new()
+        };
+
+        if existing.iter().any(|pin| pin == "nixpkgs") {
+            tracing::info!("`nixpkgs` is already pinned");
+        } else {
+            self.run_command(npins_command().args([
+                "add",
+                "--name",
+                "nixpkgs",
+                "channel",
+                self.nixpkgs_channel,
+            ]))?;
+        }
+
+        if existing.iter().any(|pin| pin == "npingler") {
+            tracing::info!("`npingler` is already pinned");
+        } else {
+            let (owner, repo) = self.npingler_repo.split_once('/').ok_or_else(|| {
+                miette!(
+                    "`--npingler-repo` should be `OWNER/REPO`: {}",
+                    self.npingler_repo
+                )
+            })?;
+            self.run_command(npins_command().args([
+                "add", "--name", "npingler", "github", owner, repo, "--branch", "main",
+            ]))?;
+        }
+
+        Ok(())
+    }
+
+    /// The packages to put in the new profile.
+    fn packages(&self) -> miette::Result<Vec<String>> {
+        if !self.import_profile {
+            return Ok(DEFAULT_PACKAGES
+                .iter()
+                .map(|name| (*name).to_owned())
+                .collect());
+        }
+
+        let nix = self.config.nix()?;
+        let profile = self.config.nix_profile(&nix)?;
+        let packages = nix
+            .installed_packages(&profile)
+            .wrap_err_with(|| format!("Failed to list the packages in {profile}"))?;
+        if packages.is_empty() {
+            tracing::warn!("No packages found in {profile}");
+        } else {
+            tracing::info!("Importing {} packages from {profile}", packages.len());
+        }
+        Ok(packages)
+    }
+
+    fn nix_file_contents(&self) -> String {
+        TEMPLATE
+            .replace("\"@hostname@\"", &nix_attr_name(&self.host))
+            .replace(
+                "\"@host@\"",
+                &serde_json::to_string(&self.host).expect("Strings serialize to JSON"),
+            )
+    }
+}
+
+/// Quote an attribute name if it isn't a valid Nix identifier.
+fn nix_attr_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'))
+        && !NIX_KEYWORDS.contains(&name);
+    if is_identifier {
+        name.to_owned()
+    } else {
+        serde_json::to_string(name).expect("Strings serialize to JSON")
+    }
+}
@@ -1,23 +1,36 @@
-use ::clap::Parser;
-
 mod app;
+mod build_failure;
+mod bundle;
 mod clap;
 mod cli;
+mod collisions;
 mod config;
 mod directories;
+mod doctor;
+mod eval_error;
 mod format_bulleted_list;
+mod format_diff;
 mod fs;
+mod hosts;
+mod init;
 mod nix;
+mod npins;
+mod package_list;
+mod packages_file;
 mod pins;
+mod privilege;
+mod profiles;
 mod tracing;
+mod update_preview;
 mod which;
 
 pub use format_bulleted_list::format_bulleted_list;
+pub use format_diff::format_diff;
 
 use crate::app::App;
 
 fn main() -> miette::Result<()> {
-    let args = cli::Args::parse();
+    let args = cli::Args::parse_with_sources();
     App::run(args)?;
 
     Ok(())
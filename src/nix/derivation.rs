@@ -100,4 +100,66 @@ pub struct Input {
 #[serde(rename_all = "camelCase")]
 pub struct Output {
     pub path: Utf8PathBuf,
+    /// The expected hash, for fixed-output derivations like `fetchurl`.
+    #[serde(default)]
+    pub hash: Option<String>,
+}
+
+impl Derivation {
+    /// Is this a fixed-output derivation, which is built by fetching something from the network?
+    pub fn is_fixed_output(&self) -> bool {
+        self.outputs.values().any(|output| output.hash.is_some())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A derivation's output, as `(name, path, hash)`.
+    pub type TestOutput<'a> = (&'a str, &'a str, Option<&'a str>);
+
+    /// A derivation, as `(path, outputs, input_drvs, input_srcs)`.
+    pub type TestDerivation<'a> = (&'a str, &'a [TestOutput<'a>], &'a [&'a str], &'a [&'a str]);
+
+    /// Build a `nix derivation show` closure.
+    pub fn derivations(drvs: &[TestDerivation<'_>]) -> Derivations {
+        let json = drvs
+            .iter()
+            .map(|(path, outputs, input_drvs, input_srcs)| {
+                let outputs = outputs
+                    .iter()
+                    .map(|(name, path, hash)| {
+                        (
+                            name.to_string(),
+                            serde_json::json!({ "path": path, "hash": hash }),
+                        )
+                    })
+                    .collect::<serde_json::Map<_, _>>();
+                let input_drvs = input_drvs
+                    .iter()
+                    .map(|input| {
+                        (
+                            input.to_string(),
+                            serde_json::json!({ "dynamicOutputs": {}, "outputs": ["out"] }),
+                        )
+                    })
+                    .collect::<serde_json::Map<_, _>>();
+                (
+                    path.to_string(),
+                    serde_json::json!({
+                        "args": [],
+                        "builder": "/bin/sh",
+                        "env": {},
+                        "inputDrvs": input_drvs,
+                        "inputSrcs": input_srcs,
+                        "name": super::super::drv_name(path),
+                        "outputs": outputs,
+                        "system": "x86_64-linux",
+                    }),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>();
+        serde_json::from_value(json.into()).unwrap()
+    }
 }
@@ -0,0 +1,79 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+/// The output of `nix profile list --json`.
+#[derive(Debug, Deserialize)]
+pub struct ProfileList {
+    elements: ProfileElements,
+}
+
+/// Nix 2.20 and later key elements by name; earlier versions and Lix use a list.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ProfileElements {
+    Map(BTreeMap<String, ProfileElement>),
+    List(Vec<ProfileElement>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileElement {
+    /// The attribute the element was installed from, like
+    /// `legacyPackages.x86_64-linux.hello`. Missing for elements installed from store paths.
+    #[serde(default)]
+    attr_path: Option<String>,
+    #[serde(default)]
+    store_paths: Vec<String>,
+}
+
+impl ProfileList {
+    /// Get the `nixpkgs` attribute paths of the installed packages, like `hello`.
+    pub fn attr_paths(&self) -> Vec<String> {
+        let elements: Vec<&ProfileElement> = match &self.elements {
+            ProfileElements::Map(elements) => elements.values().collect(),
+            ProfileElements::List(elements) => elements.iter().collect(),
+        };
+
+        elements
+            .into_iter()
+            .filter_map(|element| match &element.attr_path {
+                Some(attr_path) => Some(strip_system(attr_path).to_owned()),
+                None => {
+                    tracing::warn!(
+                        "Skipping profile element without an attribute path: {}",
+                        element.store_paths.join(", ")
+                    );
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Strip a Flake output prefix like `legacyPackages.x86_64-linux.` from an attribute path.
+fn strip_system(attr_path: &str) -> &str {
+    for prefix in ["legacyPackages.", "packages."] {
+        if let Some(rest) = attr_path.strip_prefix(prefix)
+            && let Some((_system, attr)) = rest.split_once('.')
+        {
+            return attr;
+        }
+    }
+    attr_path
+}
+
+/// An entry in the output of `nix-env --query --json`.
+#[derive(Debug, Deserialize)]
+pub struct NixEnvElement {
+    name: String,
+    #[serde(default)]
+    pname: Option<String>,
+}
+
+impl NixEnvElement {
+    /// The package name, which is usually (but not always) its `nixpkgs` attribute.
+    pub fn attr_path(&self) -> &str {
+        self.pname.as_deref().unwrap_or(&self.name)
+    }
+}
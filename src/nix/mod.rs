@@ -1,27 +1,60 @@
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+use std::io::BufRead;
+use std::io::BufReader;
 use std::io::ErrorKind;
 use std::os::unix::process::CommandExt as _;
 use std::process::Command;
 use std::process::Output;
 use std::process::Stdio;
+use std::sync::LazyLock;
 
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
+use command_error::ChildExt;
 use command_error::CommandExt;
 use command_error::OutputContext;
 use miette::Context;
 use miette::IntoDiagnostic;
 use miette::miette;
+use regex::Regex;
 use serde::de::DeserializeOwned;
 use tracing::instrument;
 use utf8_command::Utf8Output;
 
+/// Matches a position or a trace frame in a Nix evaluation error, like
+/// `at /path/to/default.nix:12:5:` or `… while calling the 'derivationStrict' builtin`.
+static EVAL_ERROR_CONTEXT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?m)^\s*(?:… |at (?:/|«).+:\d+:\d+:?$)").expect("Regex is valid")
+});
+
 mod registry;
 pub use registry::Registry;
 
+mod path_info;
+pub use path_info::PathInfo;
+use path_info::PathInfos;
+
+mod search;
+pub use search::SearchResult;
+
 mod derivation;
 pub use derivation::Derivation;
 pub use derivation::Derivations;
+#[cfg(test)]
+pub use derivation::tests::derivations;
+
+mod installed;
+use installed::NixEnvElement;
+use installed::ProfileList;
+
+mod offline;
+pub use offline::missing_paths;
+
+mod progress;
+use progress::ANSI_ESCAPE;
+use progress::BuildProgress;
+pub use progress::FailedBuild;
 
 use crate::config::NixExtraArgs;
 
@@ -32,38 +65,52 @@ pub struct Nix {
     /// Path to the `nix-env` binary.
     nix_env_program: Utf8PathBuf,
     extra_args: NixExtraArgs,
+    /// Avoid using the network.
+    offline: bool,
 }
 
 impl Nix {
-    pub fn new(extra_args: NixExtraArgs) -> miette::Result<Self> {
+    pub fn new(extra_args: NixExtraArgs, offline: bool) -> miette::Result<Self> {
         let nix_program = crate::which::which_global("nix")?;
         let nix_env_program = crate::which::which_global("nix-env")?;
         Ok(Self {
             nix_program,
             nix_env_program,
             extra_args,
+            offline,
         })
     }
 
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    pub fn nix_program(&self) -> &Utf8Path {
+        &self.nix_program
+    }
+
+    pub fn nix_env_program(&self) -> &Utf8Path {
+        &self.nix_env_program
+    }
+
     pub fn nix_command(&self) -> Command {
         let mut command = Command::new(&self.nix_program);
         command.arg("--extra-experimental-features");
         command.arg("nix-command");
+        if self.offline {
+            command.arg("--offline");
+            command.args(["--option", "substituters", ""]);
+        }
         command.args(self.extra_args.nix());
         command
     }
 
-    pub fn sudo_nix_command(&self) -> Command {
-        let mut command = Command::new("sudo");
-        let inner = self.nix_command();
-        command.arg(inner.get_program());
-        command.args(inner.get_args());
-        command
-    }
-
     fn nix_env_command(&self) -> Command {
         let mut command = Command::new(&self.nix_env_program);
         command.arg0("nix-env");
+        if self.offline {
+            command.args(["--option", "substituters", ""]);
+        }
         command
     }
 
@@ -79,32 +126,81 @@ impl Nix {
         command
     }
 
-    pub fn sudo_nix_env_set_command(
-        &self,
-        profile_link: &Utf8Path,
-        new_profile: &Utf8Path,
-    ) -> Command {
-        let mut command = Command::new("sudo");
-        let inner = self.nix_env_set_command(profile_link, new_profile);
-        command.arg(inner.get_program());
-        command.args(inner.get_args());
+    /// Copy store paths and their closures between stores with `nix copy`.
+    pub fn copy_command(&self) -> Command {
+        let mut command = self.nix_command();
+        command.arg("copy");
         command
     }
 
     /// Build something and return the out paths.
+    ///
+    /// Build progress is reported through `tracing`; build logs are logged at the `debug` level.
     #[instrument(level = "debug", skip(self))]
     pub fn build(&self, args: &[&str]) -> miette::Result<BTreeSet<Utf8PathBuf>> {
-        let stdout = self
+        let mut child = self
             .nix_command()
             .args(["build", "--no-link", "--print-out-paths"])
+            .args(["--log-format", "internal-json"])
             .args(self.extra_args.build())
             .args(args)
-            .stderr(Stdio::inherit())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn_checked()
+            .into_diagnostic()?;
+
+        let stderr = child
+            .child_mut()
+            .stderr
+            .take()
+            .expect("`nix build` stderr is piped");
+        let progress = std::thread::spawn(move || {
+            let mut progress = BuildProgress::default();
+            for line in BufReader::new(stderr).lines() {
+                match line {
+                    Ok(line) => progress.handle_line(&line),
+                    Err(err) => {
+                        tracing::debug!("Failed to read `nix build` output: {err}");
+                        break;
+                    }
+                }
+            }
+            progress.finish()
+        });
+
+        let output = child.output_checked_utf8();
+        let (failed, errors) = progress.join().unwrap_or_else(|_| {
+            tracing::debug!("`nix build` progress thread panicked");
+            Default::default()
+        });
+
+        let stderr = errors.join("\n");
+        match output {
+            Ok(output) => Ok(output.stdout.lines().map(Utf8PathBuf::from).collect()),
+            Err(source) if !failed.is_empty() => Err(BuildError { failed, source }.into()),
+            Err(source) if is_eval_error(&errors) => Err(EvalError {
+                help: Some(stderr.clone()),
+                stderr,
+                source,
+            }
+            .into()),
+            // Something else went wrong, like a substituter being unreachable.
+            Err(source) => Err(BuildCommandError {
+                help: Some(stderr).filter(|stderr| !stderr.is_empty()),
+                source,
+            }
+            .into()),
+        }
+    }
+
+    /// Get the build log for a derivation with `nix log`.
+    pub fn log(&self, drv: &Utf8Path) -> miette::Result<String> {
+        Ok(self
+            .nix_command()
+            .args(["log", drv.as_str()])
             .output_checked_utf8()
             .into_diagnostic()?
-            .stdout;
-
-        Ok(stdout.lines().map(Utf8PathBuf::from).collect())
+            .stdout)
     }
 
     #[instrument(level = "debug", skip(self))]
@@ -120,10 +216,29 @@ impl Nix {
 
         command
             .output_checked_as(|context: OutputContext<Output>| {
+                if !context.status().success() {
+                    let stderr = String::from_utf8_lossy(&context.output().stderr);
+                    return Err(EvalError {
+                        stderr: ANSI_ESCAPE.replace_all(&stderr, "").into_owned(),
+                        help: None,
+                        source: context.error(),
+                    });
+                }
+
                 serde_json::from_slice(&context.output().stdout)
-                    .map_err(|err| context.error_msg(err))
+                    .map_err(|err| EvalError::from(context.error_msg(err)))
             })
-            .into_diagnostic()
+            .map_err(miette::Report::from)
+    }
+
+    /// Get the output of `nix --version`, e.g. `nix (Lix, like Nix) 2.93.3`.
+    pub fn version(&self) -> miette::Result<String> {
+        program_version(&mut Command::new(&self.nix_program))
+    }
+
+    /// Get the output of `nix-env --version`.
+    pub fn nix_env_version(&self) -> miette::Result<String> {
+        program_version(&mut self.nix_env_command())
     }
 
     /// Get a configuration setting by name.
@@ -155,7 +270,7 @@ impl Nix {
         Utf8Path::new("/etc/nix/registry.json")
     }
 
-    pub fn parse_registry(&self, path: &Utf8Path) -> miette::Result<Option<Registry>> {
+    pub fn parse_registry(path: &Utf8Path) -> miette::Result<Option<Registry>> {
         match fs_err::read_to_string(path) {
             Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
             contents => {
@@ -187,6 +302,91 @@ impl Nix {
             .into_diagnostic()
     }
 
+    /// Get information for a derivation and every derivation in its build closure.
+    pub fn derivation_closure(&self, path: &Utf8Path) -> miette::Result<Derivations> {
+        self.nix_command()
+            .args(["derivation", "show", "--recursive", "--"])
+            .arg(path)
+            .output_checked_as(|context: OutputContext<Output>| {
+                serde_json::from_slice(&context.output().stdout)
+                    .map_err(|err| context.error_msg(err))
+            })
+            .into_diagnostic()
+    }
+
+    /// Search the packages in a Nix file (like a pinned `nixpkgs` source) with `nix search`.
+    ///
+    /// Returns attribute paths and the matching packages.
+    #[instrument(level = "debug", skip(self))]
+    pub fn search(
+        &self,
+        file: &Utf8Path,
+        terms: &[String],
+    ) -> miette::Result<BTreeMap<String, SearchResult>> {
+        self.nix_command()
+            .args(["search", "--json", "--file"])
+            .arg(file)
+            .arg("")
+            .args(terms)
+            .output_checked_as(|context: OutputContext<Output>| {
+                serde_json::from_slice(&context.output().stdout)
+                    .map_err(|err| context.error_msg(err))
+            })
+            .into_diagnostic()
+    }
+
+    /// Get information about a store path with `nix path-info`.
+    pub fn path_info(&self, path: &Utf8Path) -> miette::Result<PathInfo> {
+        self.nix_command()
+            .args(["path-info", "--json", "--"])
+            .arg(path)
+            .output_checked_as(|context: OutputContext<Output>| {
+                serde_json::from_slice::<PathInfos>(&context.output().stdout)
+                    .map_err(|err| context.error_msg(err))
+            })
+            .into_diagnostic()?
+            .into_first()
+            .ok_or_else(|| miette!("No path info given for {path}?"))
+    }
+
+    /// Get the `nixpkgs` attribute paths of the packages installed in a profile, with
+    /// `nix profile list` or `nix-env --query` depending on the profile's format.
+    #[instrument(level = "debug", skip(self))]
+    pub fn installed_packages(&self, profile: &Utf8Path) -> miette::Result<Vec<String>> {
+        let mut packages = if profile.join("manifest.json").exists() {
+            self.nix_command()
+                .args(["profile", "list", "--json", "--profile"])
+                .arg(profile)
+                .output_checked_as(|context: OutputContext<Output>| {
+                    serde_json::from_slice::<ProfileList>(&context.output().stdout)
+                        .map_err(|err| context.error_msg(err))
+                })
+                .into_diagnostic()?
+                .attr_paths()
+        } else if profile.join("manifest.nix").exists() {
+            self.nix_env_command()
+                .args(["--query", "--json", "--profile"])
+                .arg(profile)
+                .output_checked_as(|context: OutputContext<Output>| {
+                    serde_json::from_slice::<BTreeMap<String, NixEnvElement>>(
+                        &context.output().stdout,
+                    )
+                    .map_err(|err| context.error_msg(err))
+                })
+                .into_diagnostic()?
+                .values()
+                .map(|element| element.attr_path().to_owned())
+                .collect()
+        } else {
+            return Err(miette!(
+                "{profile} wasn't created by `nix profile` or `nix-env`, so its packages can't be listed"
+            ));
+        };
+        packages.sort();
+        packages.dedup();
+        Ok(packages)
+    }
+
     pub fn derivation_info(&self, path: &Utf8Path) -> miette::Result<Derivation> {
         self.derivation_infos(std::iter::once(path))?
             .0
@@ -195,3 +395,173 @@ impl Nix {
             .ok_or_else(|| miette!("No derivation info given for {path}?"))
     }
 }
+
+/// Get a derivation's name from its path, e.g. `hello-2.12.1` from
+/// `/nix/store/...-hello-2.12.1.drv`.
+pub fn drv_name(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let name = name.split_once('-').map(|(_, name)| name).unwrap_or(name);
+    name.strip_suffix(".drv").unwrap_or(name).to_owned()
+}
+
+/// Split a package name into its name and version like `builtins.parseDrvName`, e.g.
+/// `ripgrep` and `14.1.0` from `ripgrep-14.1.0`.
+///
+/// The version starts after the first dash which isn't followed by a letter.
+pub fn parse_name(name: &str) -> (&str, Option<&str>) {
+    name.match_indices('-')
+        .find(|(index, _)| {
+            name[index + 1..]
+                .chars()
+                .next()
+                .is_some_and(|c| !c.is_alphabetic())
+        })
+        .map(|(index, _)| (&name[..index], Some(&name[index + 1..])))
+        .unwrap_or((name, None))
+}
+
+/// The Nix store directory, `$NIX_STORE_DIR` or `/nix/store`.
+pub fn store_dir() -> Utf8PathBuf {
+    std::env::var("NIX_STORE_DIR")
+        .unwrap_or_else(|_| "/nix/store".to_owned())
+        .into()
+}
+
+/// Get the top-level store path containing a path, e.g. `/nix/store/...-ripgrep-14.1.0` from
+/// `/nix/store/...-ripgrep-14.1.0/bin/rg`.
+pub fn store_path_root(path: &Utf8Path) -> Option<Utf8PathBuf> {
+    let store_dir = store_dir();
+    let name = path.strip_prefix(&store_dir).ok()?.components().next()?;
+    Some(store_dir.join(name))
+}
+
+/// `nix build` failed because some derivations failed to build.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error(
+    "{} {} failed to build",
+    failed.len(),
+    if failed.len() == 1 { "derivation" } else { "derivations" }
+)]
+pub struct BuildError {
+    pub failed: Vec<FailedBuild>,
+    #[source]
+    source: command_error::Error,
+}
+
+/// `nix build` failed without an evaluation or build error, e.g. because a substituter is
+/// unreachable.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("Failed to run `nix build`")]
+pub struct BuildCommandError {
+    /// Nix's error output, without colors.
+    #[help]
+    help: Option<String>,
+    #[source]
+    source: command_error::Error,
+}
+
+/// Nix failed to evaluate an expression.
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("Nix evaluation failed")]
+pub struct EvalError {
+    /// Nix's error output, without colors.
+    pub stderr: String,
+    #[help]
+    help: Option<String>,
+    #[source]
+    source: command_error::Error,
+}
+
+impl From<command_error::Error> for EvalError {
+    fn from(source: command_error::Error) -> Self {
+        Self {
+            stderr: String::new(),
+            help: None,
+            source,
+        }
+    }
+}
+
+/// Are any of these error messages from evaluating a Nix expression?
+///
+/// Evaluation errors point at a position in a Nix file or include an evaluation trace.
+fn is_eval_error(errors: &[String]) -> bool {
+    errors
+        .iter()
+        .filter(|error| error.starts_with("error:"))
+        .any(|error| EVAL_ERROR_CONTEXT.is_match(error))
+}
+
+/// Get the output of `program --version`.
+pub fn program_version(command: &mut Command) -> miette::Result<String> {
+    Ok(command
+        .arg("--version")
+        .output_checked_utf8()
+        .into_diagnostic()?
+        .stdout
+        .trim()
+        .to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drv_name() {
+        assert_eq!(
+            drv_name("/nix/store/4q2xkx8l0fp9cq9yl2m6ypmhhn2rx1il-ripgrep-14.1.0.drv"),
+            "ripgrep-14.1.0"
+        );
+        assert_eq!(
+            drv_name("/nix/store/4q2xkx8l0fp9cq9yl2m6ypmhhn2rx1il-ripgrep-14.1.0-man"),
+            "ripgrep-14.1.0-man"
+        );
+    }
+
+    #[test]
+    fn test_parse_name() {
+        assert_eq!(parse_name("ripgrep-14.1.0"), ("ripgrep", Some("14.1.0")));
+        assert_eq!(
+            parse_name("python3.12-black-24.1.0"),
+            ("python3.12-black", Some("24.1.0"))
+        );
+        assert_eq!(
+            parse_name("nix-index-unstable-2024-01-01"),
+            ("nix-index-unstable", Some("2024-01-01"))
+        );
+        assert_eq!(parse_name("hello"), ("hello", None));
+        assert_eq!(parse_name("git-lfs"), ("git-lfs", None));
+    }
+
+    #[test]
+    fn test_is_eval_error() {
+        let errors = |errors: &[&str]| {
+            errors
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(is_eval_error(&errors(&[
+            "error: undefined variable 'pkgs'\n       at /home/user/.config/npingler/default.nix:3:5:\n            2| {\n            3|   pkgs.hello\n             |   ^"
+        ])));
+        assert!(is_eval_error(&errors(&[
+            "error:\n       … while calling the 'derivationStrict' builtin\n         at «nix-internal»/derivation-internal.nix:37:12:\n\n       error: attribute 'helo' missing"
+        ])));
+
+        assert!(!is_eval_error(&errors(&[])));
+        assert!(!is_eval_error(&errors(&[
+            "warning: error: unable to download 'https://cache.nixos.org/nix-cache-info': Couldn't resolve host name (6)"
+        ])));
+        assert!(!is_eval_error(&errors(&[
+            "error: unable to download 'https://cache.nixos.org/abc.narinfo': Couldn't resolve host name (6)"
+        ])));
+        assert!(!is_eval_error(&errors(&[
+            "error: unrecognised flag '--foo'"
+        ])));
+        assert!(!is_eval_error(&errors(&[
+            "error: writing to file: No space left on device"
+        ])));
+    }
+}
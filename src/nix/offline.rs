@@ -0,0 +1,169 @@
+use std::fmt::Display;
+
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use rustc_hash::FxHashSet;
+
+use super::Derivations;
+
+/// A path needed to build a derivation which isn't in the Nix store and can't be built without
+/// network access.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MissingPath {
+    /// A source path (like a file in a Nix expression) that isn't in the Nix store.
+    Source(Utf8PathBuf),
+    /// An output of a fixed-output derivation (like `fetchurl`) that would need to be
+    /// downloaded.
+    Fetch {
+        drv: Utf8PathBuf,
+        output: Utf8PathBuf,
+    },
+}
+
+impl Display for MissingPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MissingPath::Source(path) => write!(f, "{path} (source)"),
+            MissingPath::Fetch { drv, output } => write!(f, "{output} (fetched by {drv})"),
+        }
+    }
+}
+
+/// Find the paths that would need to be downloaded to build `root`, an `--recursive` closure of
+/// which is in `derivations`.
+///
+/// Derivations whose outputs are already in the Nix store aren't traversed, because their inputs
+/// aren't needed.
+pub fn missing_paths(derivations: &Derivations, root: &Utf8Path) -> Vec<MissingPath> {
+    let mut missing = Vec::new();
+    let mut seen = FxHashSet::default();
+    let mut queue = vec![(root.to_owned(), None::<Vec<String>>)];
+
+    while let Some((drv_path, wanted)) = queue.pop() {
+        let Some(drv) = derivations.0.get(drv_path.as_path()) else {
+            tracing::debug!(%drv_path, "Derivation missing from closure");
+            continue;
+        };
+
+        let outputs = drv
+            .outputs
+            .iter()
+            .filter(|(name, _)| {
+                wanted
+                    .as_ref()
+                    .is_none_or(|wanted| wanted.iter().any(|wanted| wanted == *name))
+            })
+            .map(|(_, output)| output.path.clone())
+            .filter(|path| !path.exists())
+            .collect::<Vec<_>>();
+        if outputs.is_empty() {
+            continue;
+        }
+
+        if !seen.insert(drv_path.clone()) {
+            continue;
+        }
+
+        if drv.is_fixed_output() {
+            missing.extend(outputs.into_iter().map(|output| MissingPath::Fetch {
+                drv: drv_path.clone(),
+                output,
+            }));
+            continue;
+        }
+
+        missing.extend(
+            drv.input_srcs
+                .iter()
+                .filter(|path| !path.exists())
+                .map(|path| MissingPath::Source(path.clone())),
+        );
+
+        for (input, input_outputs) in &drv.input_drvs {
+            queue.push((input.clone(), Some(input_outputs.outputs.clone())));
+        }
+    }
+
+    missing.sort_by_key(|path| path.to_string());
+    missing.dedup();
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nix::derivations;
+
+    #[test]
+    fn test_missing_paths() {
+        let present = std::env::temp_dir();
+        let present = present.to_str().unwrap();
+        let closure = derivations(&[
+            (
+                "/nix/store/aaa-profile.drv",
+                &[("out", "/nix/store/aaa-profile", None)],
+                &[
+                    "/nix/store/bbb-hello-2.12.drv",
+                    "/nix/store/ccc-ripgrep-14.1.0.drv",
+                ],
+                &["/nix/store/ddd-builder.sh"],
+            ),
+            (
+                "/nix/store/bbb-hello-2.12.drv",
+                &[("out", "/nix/store/bbb-hello-2.12", None)],
+                &["/nix/store/eee-hello-2.12.tar.gz.drv"],
+                &[],
+            ),
+            (
+                "/nix/store/eee-hello-2.12.tar.gz.drv",
+                &[(
+                    "out",
+                    "/nix/store/eee-hello-2.12.tar.gz",
+                    Some("sha256-abc"),
+                )],
+                &[],
+                &[],
+            ),
+            // Already built, so its inputs aren't needed.
+            (
+                "/nix/store/ccc-ripgrep-14.1.0.drv",
+                &[("out", present, None)],
+                &["/nix/store/fff-ripgrep-14.1.0.tar.gz.drv"],
+                &[],
+            ),
+            (
+                "/nix/store/fff-ripgrep-14.1.0.tar.gz.drv",
+                &[(
+                    "out",
+                    "/nix/store/fff-ripgrep-14.1.0.tar.gz",
+                    Some("sha256-def"),
+                )],
+                &[],
+                &[],
+            ),
+        ]);
+
+        assert_eq!(
+            missing_paths(&closure, Utf8Path::new("/nix/store/aaa-profile.drv")),
+            [
+                MissingPath::Source("/nix/store/ddd-builder.sh".into()),
+                MissingPath::Fetch {
+                    drv: "/nix/store/eee-hello-2.12.tar.gz.drv".into(),
+                    output: "/nix/store/eee-hello-2.12.tar.gz".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_paths_already_built() {
+        let present = std::env::temp_dir();
+        let closure = derivations(&[(
+            "/nix/store/aaa-profile.drv",
+            &[("out", present.to_str().unwrap(), None)],
+            &[],
+            &["/nix/store/ddd-builder.sh"],
+        )]);
+        assert!(missing_paths(&closure, Utf8Path::new("/nix/store/aaa-profile.drv")).is_empty());
+    }
+}
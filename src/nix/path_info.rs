@@ -0,0 +1,34 @@
+use std::collections::BTreeMap;
+
+use camino::Utf8PathBuf;
+use serde::Deserialize;
+
+/// The output of `nix path-info --json`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum PathInfos {
+    /// Nix 2.19 and later key paths by store path.
+    Map(BTreeMap<Utf8PathBuf, PathInfo>),
+    /// Earlier versions and Lix use a list.
+    List(Vec<PathInfo>),
+}
+
+impl PathInfos {
+    pub fn into_first(self) -> Option<PathInfo> {
+        match self {
+            PathInfos::Map(infos) => infos.into_values().next(),
+            PathInfos::List(infos) => infos.into_iter().next(),
+        }
+    }
+}
+
+/// Information about a store path.
+#[derive(Debug, Deserialize)]
+pub struct PathInfo {
+    /// The store paths this path references.
+    #[serde(default)]
+    pub references: Vec<Utf8PathBuf>,
+    /// The derivation which built this path, if known.
+    #[serde(default)]
+    pub deriver: Option<Utf8PathBuf>,
+}
@@ -0,0 +1,477 @@
+use std::collections::VecDeque;
+use std::sync::LazyLock;
+use std::time::Duration;
+use std::time::Instant;
+
+use camino::Utf8PathBuf;
+use regex::Regex;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+
+use super::drv_name;
+
+/// How often to log a progress summary while nothing else is happening.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many lines of each build's log to keep, in case the build fails.
+const LOG_TAIL_LINES: usize = 1000;
+
+/// Matches ANSI escape sequences in Nix's messages.
+pub static ANSI_ESCAPE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").expect("Regex is valid"));
+
+/// Matches the error messages Nix and Lix print when a derivation's builder fails.
+///
+/// Derivations which can't be built because a dependency failed (`Cannot build '…'`) aren't
+/// matched; they're shown as the dependency chain to the derivation which actually failed.
+static BUILD_FAILED: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"builder for '(/[^']+\.drv)' failed").expect("Regex is valid"));
+
+// Activity and result types for `--log-format internal-json`.
+// See: https://git.lix.systems/lix-project/lix/src/branch/main/lix/libutil/logging.hh
+
+// Activity types.
+const ACT_FILE_TRANSFER: u64 = 101;
+const ACT_COPY_PATHS: u64 = 103;
+const ACT_BUILDS: u64 = 104;
+const ACT_BUILD: u64 = 105;
+
+// Result types.
+const RES_BUILD_LOG_LINE: u64 = 101;
+const RES_SET_PHASE: u64 = 104;
+const RES_PROGRESS: u64 = 105;
+const RES_POST_BUILD_LOG_LINE: u64 = 107;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum Message {
+    Msg {
+        level: u64,
+        msg: String,
+    },
+    Start {
+        id: u64,
+        #[serde(rename = "type")]
+        activity_type: u64,
+        #[serde(default)]
+        fields: Vec<Field>,
+    },
+    Stop {
+        id: u64,
+    },
+    Result {
+        id: u64,
+        #[serde(rename = "type")]
+        result_type: u64,
+        #[serde(default)]
+        fields: Vec<Field>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Field {
+    Int(u64),
+    String(String),
+}
+
+impl Field {
+    fn as_int(&self) -> Option<u64> {
+        match self {
+            Field::Int(int) => Some(*int),
+            Field::String(_) => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Field::Int(_) => None,
+            Field::String(string) => Some(string),
+        }
+    }
+}
+
+/// Done/expected counts for an activity, from its latest progress result.
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    done: u64,
+    expected: u64,
+}
+
+impl Counts {
+    fn from_fields(fields: &[Field]) -> Option<Self> {
+        Some(Self {
+            done: fields.first()?.as_int()?,
+            expected: fields.get(1)?.as_int()?,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Activity {
+    activity_type: u64,
+    /// For builds, the derivation path.
+    drv: Option<String>,
+    /// For builds, the derivation name.
+    name: Option<String>,
+    progress: Counts,
+}
+
+/// A derivation which failed to build.
+#[derive(Debug, Clone)]
+pub struct FailedBuild {
+    pub drv: Utf8PathBuf,
+    /// The last lines of the build log, as reported while building.
+    pub log_tail: Vec<String>,
+}
+
+/// Tracks the progress of a `nix build --log-format internal-json` and reports it through
+/// `tracing`.
+#[derive(Debug)]
+pub struct BuildProgress {
+    activities: FxHashMap<u64, Activity>,
+    /// Downloads which have finished, so their bytes still count after they stop.
+    downloaded: Counts,
+    last_summary: Instant,
+    last_summary_text: String,
+    /// The last lines of each build's log, keyed by derivation path.
+    logs: FxHashMap<String, VecDeque<String>>,
+    /// Derivations reported as failed, in order.
+    failed: Vec<String>,
+    /// Error messages, which are reported when the build finishes.
+    errors: Vec<String>,
+}
+
+impl Default for BuildProgress {
+    fn default() -> Self {
+        Self {
+            activities: FxHashMap::default(),
+            downloaded: Counts::default(),
+            last_summary: Instant::now(),
+            last_summary_text: String::new(),
+            logs: FxHashMap::default(),
+            failed: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl BuildProgress {
+    /// Handle a line of `nix` stderr.
+    pub fn handle_line(&mut self, line: &str) {
+        let Some(json) = line.strip_prefix("@nix ") else {
+            // Not a structured message, e.g. output from `builtins.trace`, or an error from
+            // before Nix set up its logger, like an unknown flag.
+            let plain = ANSI_ESCAPE.replace_all(line, "");
+            if plain.starts_with("error:") {
+                self.errors.push(plain.into_owned());
+            }
+            tracing::info!("{line}");
+            return;
+        };
+
+        match serde_json::from_str::<Message>(json) {
+            Ok(message) => self.handle_message(message),
+            Err(err) => tracing::debug!("Failed to parse Nix log message {json:?}: {err}"),
+        }
+
+        if self.last_summary.elapsed() >= SUMMARY_INTERVAL {
+            self.summarize();
+        }
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        match message {
+            Message::Msg { level, msg } => {
+                if level == 0 {
+                    let plain = ANSI_ESCAPE.replace_all(&msg, "").into_owned();
+                    for captures in BUILD_FAILED.captures_iter(&plain) {
+                        let drv = captures[1].to_owned();
+                        if !self.failed.contains(&drv) {
+                            self.failed.push(drv);
+                        }
+                    }
+                    self.errors.push(plain);
+                }
+
+                match level {
+                    // Errors are reported when the build finishes.
+                    0 => tracing::debug!("{msg}"),
+                    1 => tracing::warn!("{msg}"),
+                    2 | 3 => tracing::info!("{msg}"),
+                    _ => tracing::debug!("{msg}"),
+                }
+            }
+            Message::Start {
+                id,
+                activity_type,
+                fields,
+            } => {
+                let drv = (activity_type == ACT_BUILD)
+                    .then(|| fields.first().and_then(Field::as_str).map(str::to_owned))
+                    .flatten();
+                let name = drv.as_deref().map(drv_name);
+                self.activities.insert(
+                    id,
+                    Activity {
+                        activity_type,
+                        drv,
+                        name: name.clone(),
+                        progress: Counts::default(),
+                    },
+                );
+                if let Some(name) = name {
+                    let message = format!("Building {name} {}", self.summary());
+                    tracing::info!("{}", message.trim_end());
+                }
+            }
+            Message::Stop { id } => {
+                if let Some(activity) = self.activities.remove(&id)
+                    && activity.activity_type == ACT_FILE_TRANSFER
+                {
+                    self.downloaded.done += activity.progress.done;
+                    self.downloaded.expected += activity.progress.expected;
+                }
+            }
+            Message::Result {
+                id,
+                result_type,
+                fields,
+            } => match result_type {
+                RES_BUILD_LOG_LINE | RES_POST_BUILD_LOG_LINE => {
+                    if let Some(line) = fields.first().and_then(Field::as_str) {
+                        let name = self.build_name(id);
+                        tracing::debug!("{name}> {line}");
+                        if let Some(drv) = self
+                            .activities
+                            .get(&id)
+                            .and_then(|activity| activity.drv.clone())
+                        {
+                            let log = self.logs.entry(drv).or_default();
+                            if log.len() >= LOG_TAIL_LINES {
+                                log.pop_front();
+                            }
+                            log.push_back(line.to_owned());
+                        }
+                    }
+                }
+                RES_SET_PHASE => {
+                    if let Some(phase) = fields.first().and_then(Field::as_str) {
+                        let name = self.build_name(id);
+                        tracing::debug!("{name}: {phase}");
+                    }
+                }
+                RES_PROGRESS => {
+                    if let Some(activity) = self.activities.get_mut(&id)
+                        && let Some(progress) = Counts::from_fields(&fields)
+                    {
+                        activity.progress = progress;
+                    }
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn build_name(&self, id: u64) -> &str {
+        self.activities
+            .get(&id)
+            .and_then(|activity| activity.name.as_deref())
+            .unwrap_or("nix")
+    }
+
+    fn counts(&self, activity_type: u64) -> Counts {
+        self.activities
+            .values()
+            .filter(|activity| activity.activity_type == activity_type)
+            .fold(Counts::default(), |acc, activity| Counts {
+                done: acc.done + activity.progress.done,
+                expected: acc.expected + activity.progress.expected,
+            })
+    }
+
+    /// A compact summary, like `[3/10 built, 1/4 copied, 12.3 MiB/40.0 MiB downloaded]`.
+    fn summary(&self) -> String {
+        let mut parts = Vec::new();
+
+        let builds = self.counts(ACT_BUILDS);
+        if builds.expected > 0 {
+            parts.push(format!("{}/{} built", builds.done, builds.expected));
+        }
+
+        let copies = self.counts(ACT_COPY_PATHS);
+        if copies.expected > 0 {
+            parts.push(format!("{}/{} copied", copies.done, copies.expected));
+        }
+
+        let transfers = self.counts(ACT_FILE_TRANSFER);
+        let downloaded = Counts {
+            done: self.downloaded.done + transfers.done,
+            expected: self.downloaded.expected + transfers.expected,
+        };
+        if downloaded.expected > 0 {
+            parts.push(format!(
+                "{}/{} downloaded",
+                mebibytes(downloaded.done),
+                mebibytes(downloaded.expected)
+            ));
+        }
+
+        if parts.is_empty() {
+            String::new()
+        } else {
+            format!("[{}]", parts.join(", "))
+        }
+    }
+
+    /// Log a progress summary, if anything has changed since the last one.
+    fn summarize(&mut self) {
+        self.last_summary = Instant::now();
+        let summary = self.summary();
+        if summary.is_empty() || summary == self.last_summary_text {
+            return;
+        }
+
+        let builders = self
+            .activities
+            .values()
+            .filter_map(|activity| activity.name.as_deref())
+            .collect::<Vec<_>>();
+        if builders.is_empty() {
+            tracing::info!("{summary}");
+        } else {
+            tracing::info!("{summary} building {}", builders.join(", "));
+        }
+        self.last_summary_text = summary;
+    }
+
+    /// Log a final summary and get the derivations which failed to build and any error
+    /// messages.
+    pub fn finish(mut self) -> (Vec<FailedBuild>, Vec<String>) {
+        self.summarize();
+        let failed = self
+            .failed
+            .into_iter()
+            .map(|drv| FailedBuild {
+                log_tail: self.logs.remove(&drv).map(Vec::from).unwrap_or_default(),
+                drv: Utf8PathBuf::from(drv),
+            })
+            .collect();
+        (failed, self.errors)
+    }
+}
+
+fn mebibytes(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle_lines(lines: &str) -> BuildProgress {
+        let mut progress = BuildProgress::default();
+        for line in lines.lines() {
+            progress.handle_line(line);
+        }
+        progress
+    }
+
+    #[test]
+    fn test_failed_build_with_ansi_colors() {
+        // From `nix build --log-format internal-json`, Nix 2.24.
+        let progress = handle_lines(
+            r#"@nix {"action":"start","fields":["/nix/store/abc-hello-2.12.drv","",1,1],"id":7,"level":3,"parent":0,"text":"building '/nix/store/abc-hello-2.12.drv'","type":105}
+@nix {"action":"result","fields":["checking for gcc... no"],"id":7,"type":101}
+@nix {"action":"result","fields":["configure: error: no acceptable C compiler found"],"id":7,"type":101}
+@nix {"action":"stop","id":7}
+@nix {"action":"msg","level":0,"msg":"\u001b[31;1merror:\u001b[0m builder for '\u001b[35;1m/nix/store/abc-hello-2.12.drv\u001b[0m' failed with exit code 1;\n       last 2 log lines:\n       > checking for gcc... no"}"#,
+        );
+        let (failed, errors) = progress.finish();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].drv, "/nix/store/abc-hello-2.12.drv");
+        assert_eq!(
+            failed[0].log_tail,
+            [
+                "checking for gcc... no",
+                "configure: error: no acceptable C compiler found"
+            ]
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("error: builder for '/nix/store/abc-hello-2.12.drv' failed"),
+            "ANSI escapes are removed: {:?}",
+            errors[0]
+        );
+    }
+
+    #[test]
+    fn test_dependency_failed() {
+        // Nix and Lix report dependents of a failed build with `Cannot build`, but only the
+        // derivation whose builder failed is the cause.
+        let progress = handle_lines(
+            r#"@nix {"action":"msg","level":0,"msg":"\u001b[31;1merror:\u001b[0m builder for '\u001b[35;1m/nix/store/abc-dep-1.0.drv\u001b[0m' failed with exit code 2"}
+@nix {"action":"msg","level":0,"msg":"\u001b[31;1merror:\u001b[0m Cannot build '\u001b[35;1m/nix/store/def-top-1.0.drv\u001b[0m'.\n       Reason: \u001b[31;1m1 dependency failed\u001b[0m."}
+@nix {"action":"msg","level":0,"msg":"error: builder for '/nix/store/abc-dep-1.0.drv' failed with exit code 2"}"#,
+        );
+        let (failed, errors) = progress.finish();
+        let drvs = failed
+            .iter()
+            .map(|build| build.drv.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(drvs, ["/nix/store/abc-dep-1.0.drv"]);
+        assert_eq!(errors.len(), 3);
+    }
+
+    #[test]
+    fn test_results_for_unknown_activities() {
+        // Results can arrive for activities we never saw start, e.g. after a `stop`.
+        let progress = handle_lines(
+            r#"@nix {"action":"result","fields":["orphaned log line"],"id":42,"type":101}
+@nix {"action":"result","fields":["buildPhase"],"id":42,"type":104}
+@nix {"action":"result","fields":[1,2,0,0],"id":42,"type":105}
+@nix {"action":"stop","id":42}"#,
+        );
+        assert!(progress.activities.is_empty());
+        assert!(progress.logs.is_empty());
+        let (failed, errors) = progress.finish();
+        assert!(failed.is_empty());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_summary() {
+        let progress = handle_lines(
+            r#"@nix {"action":"start","fields":[],"id":1,"level":0,"parent":0,"text":"","type":104}
+@nix {"action":"result","fields":[3,10,1,0],"id":1,"type":105}
+@nix {"action":"start","fields":[],"id":2,"level":0,"parent":0,"text":"","type":101}
+@nix {"action":"result","fields":[1048576,4194304,0,0],"id":2,"type":105}
+@nix {"action":"stop","id":2}
+trace: not json
+@nix {"action":"msg","level":1,"msg":"warning: something"}
+@nix not json either"#,
+        );
+        assert_eq!(
+            progress.summary(),
+            "[3/10 built, 1.0 MiB/4.0 MiB downloaded]"
+        );
+        assert!(progress.errors.is_empty());
+    }
+
+    #[test]
+    fn test_errors() {
+        let progress = handle_lines(
+            "\u{1b}[31;1merror:\u{1b}[0m unrecognised flag '--foo'\n\
+             @nix {\"action\":\"msg\",\"level\":0,\"msg\":\"\\u001b[31;1merror:\\u001b[0m unable to download 'https://cache.nixos.org/nix-cache-info'\"}",
+        );
+        let (failed, errors) = progress.finish();
+        assert!(failed.is_empty());
+        assert_eq!(
+            errors,
+            [
+                "error: unrecognised flag '--foo'",
+                "error: unable to download 'https://cache.nixos.org/nix-cache-info'",
+            ]
+        );
+    }
+}
@@ -0,0 +1,21 @@
+use serde::Deserialize;
+
+/// A package in the output of `nix search --json`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SearchResult {
+    pub pname: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl SearchResult {
+    /// The package's store path name, like `ripgrep-14.1.0`.
+    pub fn store_name(&self) -> String {
+        if self.version.is_empty() {
+            self.pname.clone()
+        } else {
+            format!("{}-{}", self.pname, self.version)
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+
+use camino::Utf8Path;
+use miette::Context;
+use miette::IntoDiagnostic;
+use serde::Deserialize;
+
+/// A pinned source in `npins/sources.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Pin {
+    /// The Git revision, for Git and GitHub pins.
+    #[serde(default)]
+    pub revision: Option<String>,
+    /// The release version, for release pins.
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub hash: Option<String>,
+}
+
+impl Pin {
+    /// A short description of the pinned version, like `v1.2.3` or `567a49d1913c`.
+    pub fn short_version(&self) -> String {
+        if let Some(version) = &self.version {
+            version.clone()
+        } else if let Some(revision) = &self.revision {
+            revision.chars().take(12).collect()
+        } else if let Some(url) = &self.url {
+            url.clone()
+        } else {
+            self.hash.clone().unwrap_or_else(|| "unknown".to_owned())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Sources {
+    pins: BTreeMap<String, Pin>,
+}
+
+/// Read the pins in an `npins/sources.json`.
+pub fn read_sources(path: &Utf8Path) -> miette::Result<BTreeMap<String, Pin>> {
+    let contents = fs_err::read_to_string(path).into_diagnostic()?;
+    let sources: Sources = serde_json::from_str(&contents)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to parse {path}"))?;
+    Ok(sources.pins)
+}
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+use camino::Utf8PathBuf;
+use owo_colors::OwoColorize;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::nix::drv_name;
+use crate::nix::parse_name;
+
+/// Common output names, used to group store paths into packages when there's no metadata from
+/// the configuration.
+const COMMON_OUTPUTS: &[&str] = &["bin", "dev", "devdoc", "doc", "info", "lib", "man", "out"];
+
+/// Metadata for a package in a profile, from `npingler.<host>.packageInfo`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PackageInfo {
+    name: Option<String>,
+    pname: Option<String>,
+    version: Option<String>,
+    outputs: Vec<String>,
+    description: Option<String>,
+    homepage: Option<String>,
+    licenses: Vec<String>,
+}
+
+impl PackageInfo {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+/// A package installed in a profile.
+#[derive(Debug, Clone, Serialize)]
+pub struct InstalledPackage {
+    /// The name of the `npingler` profile the package is installed in.
+    pub profile: String,
+    pub name: String,
+    pub pname: String,
+    pub version: Option<String>,
+    /// Installed output names to store paths.
+    pub outputs: BTreeMap<String, Utf8PathBuf>,
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub licenses: Vec<String>,
+}
+
+impl InstalledPackage {
+    fn new(profile: &str, name: String, info: Option<PackageInfo>) -> Self {
+        let (pname, version) = parse_name(&name);
+        let (pname, version) = (pname.to_owned(), version.map(str::to_owned));
+        match info {
+            Some(info) => Self {
+                profile: profile.to_owned(),
+                pname: info.pname.unwrap_or(pname),
+                version: info.version.or(version),
+                name,
+                outputs: BTreeMap::new(),
+                description: info.description,
+                homepage: info.homepage,
+                licenses: info.licenses,
+            },
+            None => Self {
+                profile: profile.to_owned(),
+                name,
+                pname,
+                version,
+                outputs: BTreeMap::new(),
+                description: None,
+                homepage: None,
+                licenses: Vec::new(),
+            },
+        }
+    }
+
+    /// Does the package's name or description match `filter`?
+    pub fn matches(&self, filter: &Regex) -> bool {
+        filter.is_match(&self.name)
+            || self
+                .description
+                .as_deref()
+                .is_some_and(|description| filter.is_match(description))
+    }
+
+    /// Format the package for display.
+    pub fn display(&self) -> String {
+        let mut ret = format!("{}", self.pname.bold());
+        if let Some(version) = &self.version {
+            ret.push_str(&format!(" {version}"));
+        }
+        ret.push_str(&format!(
+            " ({})",
+            self.outputs.keys().cloned().collect::<Vec<_>>().join(", ")
+        ));
+        for path in self.outputs.values() {
+            ret.push_str(&format!("\n  {}", path.dimmed()));
+        }
+        if let Some(description) = &self.description {
+            ret.push_str(&format!("\n  {description}"));
+        }
+        if !self.licenses.is_empty() {
+            ret.push_str(&format!("\n  License: {}", self.licenses.join(", ")));
+        }
+        if let Some(homepage) = &self.homepage {
+            ret.push_str(&format!("\n  Homepage: {homepage}"));
+        }
+        ret
+    }
+}
+
+/// Group the store paths in a profile into packages, using metadata from the configuration if
+/// it's available.
+///
+/// `references` are the store paths the profile's `buildEnv` references.
+pub fn group_packages(
+    profile: &str,
+    references: Vec<Utf8PathBuf>,
+    infos: Vec<PackageInfo>,
+) -> Vec<InstalledPackage> {
+    let mut remaining: BTreeMap<String, Utf8PathBuf> = references
+        .into_iter()
+        .map(|path| (drv_name(path.as_str()), path))
+        .collect();
+    let mut packages = Vec::new();
+
+    for info in infos {
+        let Some(name) = info.name.clone() else {
+            continue;
+        };
+        let mut outputs = BTreeMap::new();
+        for output in &info.outputs {
+            let store_name = if output == "out" {
+                name.clone()
+            } else {
+                format!("{name}-{output}")
+            };
+            if let Some(path) = remaining.remove(&store_name) {
+                outputs.insert(output.clone(), path);
+            }
+        }
+        // The configuration may have changed since the profile was switched to.
+        if outputs.is_empty() {
+            continue;
+        }
+        let mut package = InstalledPackage::new(profile, name, Some(info));
+        package.outputs = outputs;
+        packages.push(package);
+    }
+
+    // Group anything left over by guessing output names.
+    let mut unknown: BTreeMap<String, BTreeMap<String, Utf8PathBuf>> = BTreeMap::new();
+    for (store_name, path) in remaining {
+        let (name, output) = COMMON_OUTPUTS
+            .iter()
+            .find_map(|output| {
+                store_name
+                    .strip_suffix(&format!("-{output}"))
+                    .map(|name| (name.to_owned(), (*output).to_owned()))
+            })
+            .unwrap_or_else(|| (store_name.clone(), "out".to_owned()));
+        unknown.entry(name).or_default().insert(output, path);
+    }
+    for (name, outputs) in unknown {
+        let mut package = InstalledPackage::new(profile, name, None);
+        package.outputs = outputs;
+        packages.push(package);
+    }
+
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    packages
+}
@@ -0,0 +1,189 @@
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::miette;
+use toml_edit::Array;
+use toml_edit::DocumentMut;
+use toml_edit::Item;
+use toml_edit::Table;
+use toml_edit::Value;
+
+use crate::config::RunMode;
+use crate::format_diff;
+
+/// The name of the package list file, next to `default.nix`.
+pub const PACKAGES_FILE: &str = "packages.toml";
+
+/// A `packages.toml` file listing `nixpkgs` attribute paths to install, read by
+/// `makeProfile { packagesFile = ./packages.toml; }`.
+///
+/// ```toml
+/// # Installed on every host.
+/// packages = ["ripgrep"]
+///
+/// [host.grandiflora]
+/// packages = ["gdb"]
+/// ```
+#[derive(Debug)]
+pub struct PackagesFile {
+    path: Utf8PathBuf,
+    /// The file's original contents, empty if it doesn't exist.
+    original: String,
+    document: DocumentMut,
+}
+
+impl PackagesFile {
+    /// Read the package list in `directory`, or start an empty one if it doesn't exist.
+    pub fn read(directory: &Utf8Path) -> miette::Result<Self> {
+        let path = directory.join(PACKAGES_FILE);
+        let original = if path.exists() {
+            fs_err::read_to_string(&path).into_diagnostic()?
+        } else {
+            String::new()
+        };
+        let document = original
+            .parse()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to parse {path}"))?;
+        Ok(Self {
+            path,
+            original,
+            document,
+        })
+    }
+
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    /// A description of the list for `host`, or the shared list if `host` is `None`.
+    pub fn list_name(host: Option<&str>) -> String {
+        match host {
+            Some(host) => format!("`[host.{host}]` packages"),
+            None => "shared packages".to_owned(),
+        }
+    }
+
+    /// Get the packages for `host`, or the shared packages if `host` is `None`.
+    pub fn packages(&self, host: Option<&str>) -> Vec<String> {
+        let item = match host {
+            Some(host) => self
+                .document
+                .get("host")
+                .and_then(|hosts| hosts.get(host))
+                .and_then(|section| section.get("packages")),
+            None => self.document.get("packages"),
+        };
+        item.and_then(Item::as_array)
+            .map(|array| {
+                array
+                    .iter()
+                    .filter_map(|value| value.as_str().map(str::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Add a package to the list for `host`, or the shared list if `host` is `None`.
+    ///
+    /// Returns `false` if the package is already in the list.
+    pub fn add(&mut self, host: Option<&str>, package: &str) -> miette::Result<bool> {
+        let array = self.array_mut(host)?;
+        if array.iter().any(|value| value.as_str() == Some(package)) {
+            return Ok(false);
+        }
+        array.push(package);
+        format_array(array);
+        Ok(true)
+    }
+
+    /// Remove a package from the list for `host`, or the shared list if `host` is `None`.
+    ///
+    /// Returns `false` if the package isn't in the list.
+    pub fn remove(&mut self, host: Option<&str>, package: &str) -> miette::Result<bool> {
+        // Don't create an empty list just to remove nothing from it.
+        if !self
+            .packages(host)
+            .iter()
+            .any(|existing| existing == package)
+        {
+            return Ok(false);
+        }
+        let array = self.array_mut(host)?;
+        let Some(index) = array
+            .iter()
+            .position(|value| value.as_str() == Some(package))
+        else {
+            return Ok(false);
+        };
+        array.remove(index);
+        format_array(array);
+        Ok(true)
+    }
+
+    fn array_mut(&mut self, host: Option<&str>) -> miette::Result<&mut Array> {
+        let path = &self.path;
+        let table = match host {
+            Some(host) => {
+                let hosts = self
+                    .document
+                    .entry("host")
+                    .or_insert_with(|| {
+                        let mut table = Table::new();
+                        table.set_implicit(true);
+                        Item::Table(table)
+                    })
+                    .as_table_mut()
+                    .ok_or_else(|| miette!("`host` in {path} is not a table"))?;
+                hosts
+                    .entry(host)
+                    .or_insert_with(|| Item::Table(Table::new()))
+                    .as_table_mut()
+                    .ok_or_else(|| miette!("`host.{host}` in {path} is not a table"))?
+            }
+            None => self.document.as_table_mut(),
+        };
+
+        let packages = table
+            .entry("packages")
+            .or_insert_with(|| Item::Value(Value::Array(Array::new())));
+        let array = packages
+            .as_array_mut()
+            .ok_or_else(|| miette!("`packages` in {path} is not an array"))?;
+        Ok(array)
+    }
+
+    /// Write the changes, showing a diff.
+    ///
+    /// Returns `false` if nothing changed.
+    pub fn write(&self, run_mode: &RunMode) -> miette::Result<bool> {
+        let contents = self.document.to_string();
+        if contents == self.original {
+            return Ok(false);
+        }
+
+        let displayed = format_diff(&self.path, &self.original, &contents);
+        match run_mode {
+            RunMode::Dry => {
+                tracing::info!("Would update {}:\n{displayed}", self.path);
+            }
+            RunMode::Wet => {
+                tracing::info!("Updating {}:\n{displayed}", self.path);
+                fs_err::write(&self.path, contents).into_diagnostic()?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Put one package per line, so that diffs are readable.
+fn format_array(array: &mut Array) {
+    let empty = array.is_empty();
+    array.set_trailing_comma(!empty);
+    array.set_trailing(if empty { "" } else { "\n" });
+    for value in array.iter_mut() {
+        value.decor_mut().set_prefix("\n    ");
+        value.decor_mut().set_suffix("");
+    }
+}
@@ -0,0 +1,342 @@
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::process::Command;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use command_error::CommandExt;
+use miette::Context;
+use serde::Deserialize;
+
+/// A command used to run other commands as `root`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "PrivilegeCommandWire")]
+pub enum PrivilegeCommand {
+    #[default]
+    Sudo,
+    Doas,
+    Run0,
+    Pkexec,
+    /// Don't escalate privileges; run commands as the current user.
+    None,
+    /// A custom command and arguments, e.g. `["sudo", "--preserve-env"]`.
+    Custom(Vec<String>),
+}
+
+impl PrivilegeCommand {
+    /// The program and arguments to prefix commands with, if any.
+    pub fn argv(&self) -> Option<Vec<String>> {
+        match self {
+            PrivilegeCommand::Sudo => Some(vec!["sudo".to_owned()]),
+            PrivilegeCommand::Doas => Some(vec!["doas".to_owned()]),
+            PrivilegeCommand::Run0 => Some(vec!["run0".to_owned()]),
+            PrivilegeCommand::Pkexec => Some(vec!["pkexec".to_owned()]),
+            PrivilegeCommand::None => None,
+            PrivilegeCommand::Custom(argv) => Some(argv.clone()),
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sudo" => Some(PrivilegeCommand::Sudo),
+            "doas" => Some(PrivilegeCommand::Doas),
+            "run0" => Some(PrivilegeCommand::Run0),
+            "pkexec" => Some(PrivilegeCommand::Pkexec),
+            "none" => Some(PrivilegeCommand::None),
+            _ => None,
+        }
+    }
+}
+
+impl Display for PrivilegeCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PrivilegeCommand::Sudo => write!(f, "sudo"),
+            PrivilegeCommand::Doas => write!(f, "doas"),
+            PrivilegeCommand::Run0 => write!(f, "run0"),
+            PrivilegeCommand::Pkexec => write!(f, "pkexec"),
+            PrivilegeCommand::None => write!(f, "none"),
+            PrivilegeCommand::Custom(argv) => write!(f, "{}", shell_words::join(argv)),
+        }
+    }
+}
+
+impl FromStr for PrivilegeCommand {
+    type Err = PrivilegeCommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Self::from_name(s) {
+            Some(command) => Ok(command),
+            None => Self::custom(shell_words::split(s)?),
+        }
+    }
+}
+
+impl PrivilegeCommand {
+    fn custom(argv: Vec<String>) -> Result<Self, PrivilegeCommandError> {
+        if argv.is_empty() {
+            Err(PrivilegeCommandError::Empty)
+        } else {
+            Ok(PrivilegeCommand::Custom(argv))
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PrivilegeCommandError {
+    #[error("privilege command is empty; use `none` to run commands as the current user")]
+    Empty,
+    #[error(transparent)]
+    Split(#[from] shell_words::ParseError),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PrivilegeCommandWire {
+    Name(String),
+    Argv(Vec<String>),
+}
+
+impl TryFrom<PrivilegeCommandWire> for PrivilegeCommand {
+    type Error = PrivilegeCommandError;
+
+    fn try_from(wire: PrivilegeCommandWire) -> Result<Self, Self::Error> {
+        match wire {
+            // Split strings like `doas -u root` the same way as the command-line flag does.
+            PrivilegeCommandWire::Name(name) => name.parse(),
+            PrivilegeCommandWire::Argv(argv) => Self::custom(argv),
+        }
+    }
+}
+
+/// Are we running as `root`?
+pub fn is_root() -> bool {
+    rustix::process::geteuid().is_root()
+}
+
+/// Runs commands as `root`.
+#[derive(Debug, Clone)]
+pub struct Privilege {
+    command: PrivilegeCommand,
+    /// Are we already running as `root`?
+    is_root: bool,
+}
+
+impl Privilege {
+    pub fn new(command: PrivilegeCommand) -> Self {
+        let is_root = is_root();
+        if is_root {
+            tracing::debug!("Running as `root`, privilege escalation is not needed");
+        }
+        Self { command, is_root }
+    }
+
+    /// The argv to prefix commands with, or `None` if commands should be run as-is.
+    fn argv(&self) -> Option<Vec<String>> {
+        if self.is_root {
+            None
+        } else {
+            self.command.argv()
+        }
+    }
+
+    /// Prompt for credentials now, rather than partway through a run.
+    ///
+    /// For `sudo`, the returned guard keeps the cached credentials fresh until it's dropped.
+    /// Other privilege commands are checked by running `true` with them, which fails early if
+    /// they don't work, although commands which don't cache credentials will prompt again later.
+    pub fn acquire(&self) -> miette::Result<Option<PrivilegeKeepAlive>> {
+        let argv = match self.argv() {
+            Some(argv) => argv,
+            None => return Ok(None),
+        };
+
+        let (validate, non_interactive) = match &self.command {
+            PrivilegeCommand::Sudo => (vec!["-v"], Some(vec!["-n", "-v"])),
+            PrivilegeCommand::Doas => (vec!["true"], Some(vec!["-n", "true"])),
+            // These can't be asked not to prompt, so they're only run without a terminal in case
+            // they don't need a password.
+            _ => (vec!["true"], None),
+        };
+
+        let cached = match &non_interactive {
+            Some(non_interactive) => run_quietly(&argv, non_interactive),
+            None => !std::io::stdin().is_terminal() && run_quietly(&argv, &validate),
+        };
+
+        if !cached {
+            if !std::io::stdin().is_terminal() {
+                return Err(NonInteractiveError {
+                    command: self.command.to_string(),
+                }
+                .into());
+            }
+
+            tracing::info!("Acquiring `root` privileges with `{}`", self.command);
+            let mut command = Command::new(&argv[0]);
+            command.args(&argv[1..]).args(&validate);
+            command.status_checked().wrap_err_with(|| {
+                format!(
+                    "Failed to acquire `root` privileges with `{}`",
+                    self.command
+                )
+            })?;
+        }
+
+        match (&self.command, non_interactive) {
+            (PrivilegeCommand::Sudo, Some(refresh)) => {
+                Ok(Some(PrivilegeKeepAlive::spawn(argv, refresh)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Wrap a command so that it runs as `root`.
+    ///
+    /// Only the program and arguments of `inner` are preserved.
+    pub fn escalate(&self, inner: Command) -> Command {
+        match self.argv() {
+            Some(argv) => {
+                let mut command = Command::new(&argv[0]);
+                command.args(&argv[1..]);
+                command.arg(inner.get_program());
+                command.args(inner.get_args());
+                command
+            }
+            None => inner,
+        }
+    }
+}
+
+/// Run `argv` followed by `args` without a terminal, and check if it succeeds.
+fn run_quietly(argv: &[String], args: &[&str]) -> bool {
+    let mut command = Command::new(&argv[0]);
+    command.args(&argv[1..]).args(args);
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    command
+        .status_checked()
+        .inspect_err(|err| tracing::debug!("Failed to run without a password:\n{err}"))
+        .is_ok()
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("`{command}` needs a password, but `npingler` is not running interactively")]
+#[diagnostic(help(
+    "Authenticate with `{command}` before running `npingler`, allow `{command}` without a password, or disable `root` pinning with `--pin-registry-root false --pin-channels-root false`"
+))]
+pub struct NonInteractiveError {
+    command: String,
+}
+
+/// Keeps cached `sudo` credentials from expiring, e.g. during a long build.
+///
+/// The credentials are refreshed in a background thread until this is dropped.
+pub struct PrivilegeKeepAlive {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PrivilegeKeepAlive {
+    /// `sudo`'s default `timestamp_timeout` is 5 minutes.
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    fn spawn(argv: Vec<String>, refresh: Vec<&'static str>) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(Self::INTERVAL) {
+                let mut command = Command::new(&argv[0]);
+                command.args(&argv[1..]).args(&refresh);
+                command
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null());
+                if let Err(err) = command.status_checked() {
+                    tracing::debug!("Failed to refresh cached credentials:\n{err}");
+                }
+            }
+        });
+
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for PrivilegeKeepAlive {
+    fn drop(&mut self) {
+        // Dropping the sender wakes up the thread.
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Wrapper {
+        command: PrivilegeCommand,
+    }
+
+    fn parse_toml(toml: &str) -> PrivilegeCommand {
+        toml::from_str::<Wrapper>(toml).unwrap().command
+    }
+
+    #[test]
+    fn test_privilege_command_names() {
+        assert_eq!(parse_toml(r#"command = "doas""#), PrivilegeCommand::Doas);
+        assert_eq!(parse_toml(r#"command = "none""#), PrivilegeCommand::None);
+        assert_eq!(
+            "run0".parse::<PrivilegeCommand>().unwrap(),
+            PrivilegeCommand::Run0
+        );
+    }
+
+    #[test]
+    fn test_privilege_command_string_is_split() {
+        let expected =
+            PrivilegeCommand::Custom(vec!["doas".to_owned(), "-u".to_owned(), "root".to_owned()]);
+        assert_eq!(parse_toml(r#"command = "doas -u root""#), expected);
+        assert_eq!(
+            "doas -u root".parse::<PrivilegeCommand>().unwrap(),
+            expected
+        );
+        assert_eq!(expected.to_string(), "doas -u root");
+    }
+
+    #[test]
+    fn test_privilege_command_argv() {
+        assert_eq!(
+            parse_toml(r#"command = ["sudo", "--preserve-env"]"#),
+            PrivilegeCommand::Custom(vec!["sudo".to_owned(), "--preserve-env".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_privilege_command_empty() {
+        for toml in [r#"command = []"#, r#"command = """#, r#"command = "  ""#] {
+            let err = toml::from_str::<Wrapper>(toml).unwrap_err();
+            assert!(
+                err.message().contains("privilege command is empty"),
+                "{err}"
+            );
+            assert_eq!(err.span(), Some(10..toml.len()), "{toml}");
+        }
+        assert!("".parse::<PrivilegeCommand>().is_err());
+    }
+
+    #[test]
+    fn test_privilege_command_unbalanced_quotes() {
+        assert!(toml::from_str::<Wrapper>(r#"command = "sudo 'oops""#).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use itertools::Itertools;
+use miette::Context;
+use miette::IntoDiagnostic;
+use miette::miette;
+
+use crate::fs::resolve_symlink_utf8;
+use crate::hosts::similar_hosts;
+
+/// The name of the profile built from `npingler.<host>.packages`.
+pub const BASE_PROFILE: &str = "base";
+
+/// A Nix profile managed by `npingler`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManagedProfile {
+    /// The profile's name, as given to `--profile-name`.
+    pub name: String,
+    /// The attribute to build, relative to `npingler.<host>`.
+    pub attr: String,
+    /// The profile link to switch to the built packages.
+    pub link: Utf8PathBuf,
+}
+
+impl ManagedProfile {
+    /// The profile built from `npingler.<host>.packages`.
+    pub fn base(link: Utf8PathBuf) -> Self {
+        Self {
+            name: BASE_PROFILE.to_owned(),
+            attr: "packages".to_owned(),
+            link,
+        }
+    }
+
+    /// A profile built from `npingler.<host>.profiles.<name>`.
+    pub fn named(name: &str, link: Utf8PathBuf) -> Self {
+        Self {
+            name: name.to_owned(),
+            attr: format!("profiles.{name:?}"),
+            link,
+        }
+    }
+
+    /// A description of the profile for log messages.
+    pub fn description(&self) -> String {
+        format!("`{}` profile", self.name)
+    }
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("No profile named `{name}` for host `{host}`")]
+#[diagnostic(help("{help}"))]
+pub struct ProfileNotFound {
+    name: String,
+    host: String,
+    help: String,
+}
+
+impl ProfileNotFound {
+    pub fn new(name: &str, host: &str, profiles: &[ManagedProfile]) -> Self {
+        let names = profiles
+            .iter()
+            .map(|profile| profile.name.clone())
+            .collect::<Vec<_>>();
+        let mut help = format!(
+            "Available profiles are {}.",
+            names.iter().map(|name| format!("`{name}`")).join(", ")
+        );
+
+        let similar = similar_hosts(name, &names);
+        if !similar.is_empty() {
+            help.push_str(&format!(
+                " Did you mean {}?",
+                similar.iter().map(|name| format!("`{name}`")).join(" or ")
+            ));
+        }
+
+        Self {
+            name: name.to_owned(),
+            host: host.to_owned(),
+            help,
+        }
+    }
+}
+
+/// Check that a store path given with `switch --store-path` looks like a profile built by
+/// `makePackages`.
+///
+/// Symlinks into the store (like `./result`) are resolved, and the store path is returned.
+pub fn check_profile_store_path(path: &Utf8Path) -> miette::Result<Utf8PathBuf> {
+    let path = resolve_symlink_utf8(path.to_owned())
+        .wrap_err_with(|| format!("Failed to resolve {path}"))?;
+    let path = path.as_path();
+    let store_dir = crate::nix::store_dir();
+    if path.parent() != Some(store_dir.as_path()) {
+        return Err(miette!(
+            "{path} is not a top-level path in the Nix store ({store_dir})"
+        ));
+    }
+
+    let metadata = fs_err::metadata(path)
+        .into_diagnostic()
+        .map_err(|err| miette!("{path} is not in the Nix store: {err}"))?;
+    if !metadata.is_dir() {
+        return Err(miette!("{path} is not a directory, so it isn't a profile"));
+    }
+
+    // `buildEnv` outputs only contain directories and symlinks to other store paths.
+    for entry in fs_err::read_dir(path).into_diagnostic()? {
+        let entry = entry.into_diagnostic()?;
+        let file_type = entry.file_type().into_diagnostic()?;
+        if !file_type.is_dir() && !file_type.is_symlink() {
+            return Err(miette!(
+                help = "Profiles are built with `makePackages` or `makeProfile`",
+                "{path} doesn't look like a profile; it contains a regular file: {}",
+                entry.path().display()
+            ));
+        }
+    }
+
+    if path
+        .file_name()
+        .is_some_and(|name| !name.contains("npingler-packages"))
+    {
+        tracing::warn!("{path} doesn't look like it was built by `npingler`, switching anyways");
+    }
+
+    Ok(path.to_owned())
+}
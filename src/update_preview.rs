@@ -0,0 +1,68 @@
+use std::collections::BTreeMap;
+use std::process::Command;
+
+use camino::Utf8Path;
+use owo_colors::OwoColorize;
+
+use crate::nix::parse_name;
+use crate::npins::Pin;
+
+/// The `npins update` command to run in a configuration directory.
+pub fn npins_update_command(directory: &Utf8Path) -> Command {
+    let mut command = Command::new("npins");
+    command.current_dir(directory);
+    command.arg("update");
+    // TODO: Only run `npins` in verbose mode if `npingler` is in verbose mode?
+    command.arg("--verbose");
+    command
+}
+
+/// Pin names to short versions.
+pub fn pin_versions(pins: &BTreeMap<String, Pin>) -> BTreeMap<String, String> {
+    pins.iter()
+        .map(|(name, pin)| (name.clone(), pin.short_version()))
+        .collect()
+}
+
+/// Package names to versions, from full names like `ripgrep-14.1.0`.
+pub fn package_versions<'n>(names: impl IntoIterator<Item = &'n str>) -> BTreeMap<String, String> {
+    names
+        .into_iter()
+        .map(|name| {
+            let (pname, version) = parse_name(name);
+            (pname.to_owned(), version.unwrap_or_default().to_owned())
+        })
+        .collect()
+}
+
+/// Print the differences between two maps of names to versions under a heading.
+pub fn print_changes(
+    heading: &str,
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) {
+    let mut lines = Vec::new();
+    for (name, old_version) in old {
+        match new.get(name) {
+            Some(new_version) if new_version != old_version => {
+                lines.push(format!("  {name}: {old_version} → {new_version}"));
+            }
+            Some(_) => {}
+            None => lines.push(format!("  {}", format!("- {name} {old_version}").red())),
+        }
+    }
+    for (name, new_version) in new {
+        if !old.contains_key(name) {
+            lines.push(format!("  {}", format!("+ {name} {new_version}").green()));
+        }
+    }
+
+    if lines.is_empty() {
+        println!("{heading}: no changes");
+    } else {
+        println!("{heading}:");
+        for line in lines {
+            println!("{line}");
+        }
+    }
+}
@@ -0,0 +1,33 @@
+let
+  npins-sources = import ./npins;
+  pkgs = import npins-sources.nixpkgs {
+    overlays = [
+      (final: prev: {
+        inherit npins-sources;
+
+        npingler-lib = final.callPackage "${npins-sources.npingler}/lib" { };
+      })
+    ];
+  };
+in
+{
+  npingler = {
+    # By default, npingler uses the attr matching your hostname. Add attrs for
+    # your other machines to share this configuration between them.
+    "@hostname@" = pkgs.npingler-lib.makeProfile {
+      pins = {
+        # These get pinned in the `nix registry` so that (e.g.) `nix repl
+        # nixpkgs` uses the same version of `nixpkgs` as your profile, and in
+        # your Nix channels, so that `nix-shell -p hello` does too.
+        nixpkgs = npins-sources.nixpkgs;
+      };
+
+      # The packages to install in your profile. Packages can also be listed
+      # by name in `packages.toml`, which `npingler add` and `npingler remove`
+      # edit.
+      paths = [ ];
+      packagesFile = ./packages.toml;
+      host = "@host@";
+    };
+  };
+}